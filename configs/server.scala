@@ -0,0 +1,185 @@
+package carsier.server
+
+import java.io._
+import java.net._
+import java.nio.charset.StandardCharsets.UTF_8
+import java.nio.file._
+import scala.collection.mutable
+import scala.tools.nsc.{CompilerCommand, Global, Phase, Settings}
+import scala.tools.nsc.plugins.PluginComponent
+import scala.tools.nsc.reporters.ConsoleReporter
+
+/// a warm scalac worker on localhost, requests and replies are length prefixed
+/// so arguments may be empty or contain line breaks
+/// ```
+/// > int count, then count strings of int length and utf-8 bytes:
+/// >   ping | stop | compile <analysis file> <scalac args>...
+/// < byte 0 (ok) or 1 (error), int length, utf-8 output of the compiler
+/// ```
+/// a compile writes the top level classes each compiled source defines and uses
+/// into the analysis file, read back by `incremental.rs`
+object Main {
+  def main(args: Array[String]): Unit = {
+    val portFile = Paths.get(args(0))
+    val server = new ServerSocket(0, 50, InetAddress.getLoopbackAddress)
+    Files.write(portFile, server.getLocalPort.toString.getBytes(UTF_8))
+    var running = true
+    while (running) {
+      val socket = server.accept()
+      try {
+        val in = new DataInputStream(new BufferedInputStream(socket.getInputStream))
+        val out = new DataOutputStream(new BufferedOutputStream(socket.getOutputStream))
+        def reply(ok: Boolean, output: String): Unit = {
+          val bytes = output.getBytes(UTF_8)
+          out.writeByte(if (ok) 0 else 1)
+          out.writeInt(bytes.length)
+          out.write(bytes)
+          out.flush()
+        }
+        List.fill(in.readInt())(readString(in)) match {
+          case List("ping") => reply(true, "")
+          case List("stop") =>
+            running = false
+            reply(true, "")
+          case "compile" :: analysisFile :: compilerArgs =>
+            val (ok, output) = compile(analysisFile, compilerArgs)
+            reply(ok, output)
+          case other => reply(false, s"unknown request ${other.mkString(" ")}")
+        }
+      } catch {
+        case e: Exception => e.printStackTrace()
+      } finally {
+        socket.close()
+      }
+    }
+    server.close()
+    Files.deleteIfExists(portFile)
+  }
+
+  private def readString(in: DataInputStream): String = {
+    val bytes = new Array[Byte](in.readInt())
+    in.readFully(bytes)
+    new String(bytes, UTF_8)
+  }
+
+  /// a compiler and everything it printed for the current request
+  private class Compiler(args: List[String]) {
+    val buffer = new ByteArrayOutputStream()
+    val stream = new PrintStream(buffer, true, "UTF-8")
+    val settings = new Settings(msg => stream.println(msg))
+    val command = new CompilerCommand(args, settings)
+    lazy val reporter = new ConsoleReporter(settings, new BufferedReader(new StringReader("")), new PrintWriter(stream, true))
+    lazy val global = new AnalyzingGlobal(settings, reporter)
+    def output: String = buffer.toString("UTF-8")
+  }
+
+  /// compilers of the last few settings, the oldest is dropped first
+  private val compilers = mutable.LinkedHashMap.empty[List[String], Compiler]
+  private val maxCompilers = 4
+
+  def compile(analysisFile: String, args: List[String]): (Boolean, String) = {
+    val request = new Compiler(args)
+    if (!request.command.ok) return (false, request.output)
+    val key = request.settings.recreateArgs
+    compilers.remove(key) match {
+      case Some(cached) if run(cached, request.command.files, analysisFile, reused = true) => keep(key, cached)
+      case Some(_) =>
+        // state kept from earlier runs may be stale, errors only count from a fresh compiler
+        val fresh = new Compiler(args)
+        if (run(fresh, fresh.command.files, analysisFile, reused = false)) keep(key, fresh) else (false, fresh.output)
+      case None =>
+        if (run(request, request.command.files, analysisFile, reused = false)) keep(key, request) else (false, request.output)
+    }
+  }
+
+  private def keep(key: List[String], compiler: Compiler): (Boolean, String) = {
+    compilers(key) = compiler
+    while (compilers.size > maxCompilers) compilers -= compilers.head._1
+    (true, compiler.output)
+  }
+
+  private def run(compiler: Compiler, files: List[String], analysisFile: String, reused: Boolean): Boolean = {
+    compiler.buffer.reset()
+    try {
+      Console.withOut(compiler.stream) { Console.withErr(compiler.stream) {
+        val global = compiler.global
+        if (reused) {
+          // the output dir is on the classpath, its classes were rewritten by the last run
+          val out = new File(global.settings.outdir.value).getCanonicalPath
+          val classpath = global.settings.classpath.value.split(File.pathSeparator).map(p => new File(p).getCanonicalPath)
+          if (classpath.contains(out)) global.invalidateClassPathEntries(out)
+          compiler.reporter.reset()
+        }
+        global.recorded.clear()
+        global.recordFailed = false
+        new global.Run().compile(files)
+        val ok = !compiler.reporter.hasErrors
+        if (ok) writeAnalysis(global, analysisFile)
+        ok
+      } }
+    } catch {
+      case e: Throwable =>
+        e.printStackTrace(compiler.stream)
+        false
+    }
+  }
+
+  /// `source <path>` followed by its `define <class>` and `use <class>` lines,
+  /// no file at all if the analysis could not be recorded
+  private def writeAnalysis(global: AnalyzingGlobal, file: String): Unit = {
+    val path = Paths.get(file)
+    Files.deleteIfExists(path)
+    if (!global.recordFailed) {
+      val lines = global.recorded.toList.flatMap { case (source, (defined, used)) =>
+        s"source $source" :: defined.toList.sorted.map("define " + _) ::: used.toList.sorted.map("use " + _)
+      }
+      Files.write(path, lines.map(_ + "\n").mkString.getBytes(UTF_8))
+    }
+  }
+}
+
+/// a compiler recording, after typer, the top level classes each source defines and uses
+class AnalyzingGlobal(settings: Settings, reporter: ConsoleReporter) extends Global(settings, reporter) {
+  /// by canonical source path, of the units of the last run
+  val recorded = mutable.LinkedHashMap.empty[String, (mutable.Set[String], mutable.Set[String])]
+  var recordFailed = false
+
+  private object analyzer extends PluginComponent {
+    val global: AnalyzingGlobal.this.type = AnalyzingGlobal.this
+    val runsAfter = List("typer")
+    override val runsBefore = List("patmat")
+    val phaseName = "carsier-analysis"
+    def newPhase(prev: Phase): Phase = new StdPhase(prev) {
+      def apply(unit: CompilationUnit): Unit = record(unit)
+    }
+  }
+
+  override protected def computeInternalPhases(): Unit = {
+    super.computeInternalPhases()
+    phasesSet += analyzer
+  }
+
+  private def record(unit: CompilationUnit): Unit = try {
+    val file = unit.source.file.file
+    if (file == null) recordFailed = true
+    else {
+      val defined = mutable.Set.empty[String]
+      val used = mutable.Set.empty[String]
+      def addUse(sym: Symbol): Unit = if (sym != null && sym != NoSymbol) {
+        val top = sym.enclosingTopLevelClass
+        if (top != NoSymbol && !top.isPackageClass) used += top.fullName('/')
+      }
+      unit.body.foreach { tree =>
+        tree match {
+          case d: ImplDef if d.symbol != null && d.symbol.isTopLevel => defined += d.symbol.fullName('/')
+          case _ =>
+        }
+        addUse(tree.symbol)
+        if (tree.tpe != null) tree.tpe.foreach(t => addUse(t.typeSymbol))
+      }
+      recorded(file.getCanonicalPath) = (defined, used --= defined)
+    }
+  } catch {
+    case _: Exception => recordFailed = true
+  }
+}
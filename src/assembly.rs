@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use crate::{build, jar, resolve, toolchain};
+use crate::config::{PackageConfig, Assembly, MergeRule, MergeStrategy, ShadeRule};
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  /// output jar, defaults to `target/assembly/<name>-<version>.jar`
+  #[clap(short = "o", long)]
+  pub output: Option<PathBuf>,
+}
+
+fn default_rules() -> Vec<MergeRule> {
+  use MergeStrategy::*;
+  vec![
+    ("META-INF/MANIFEST.MF", Discard),
+    ("META-INF/*.SF", Discard),
+    ("META-INF/*.DSA", Discard),
+    ("META-INF/*.RSA", Discard),
+    ("META-INF/*.EC", Discard),
+    ("META-INF/INDEX.LIST", Discard),
+    ("**/module-info.class", Discard),
+    ("META-INF/services/*", Service),
+    ("reference.conf", Concat),
+  ].into_iter().map(|(pattern, strategy)| MergeRule { pattern: pattern.to_string(), strategy }).collect()
+}
+
+struct Merger {
+  rules: Vec<(glob::Pattern, MergeStrategy)>,
+}
+impl Merger {
+  fn new(config: &Assembly) -> Result<Self, anyhow::Error> {
+    let rules = config.merge.iter().cloned().chain(default_rules())
+      .map(|r| Ok((glob::Pattern::new(&r.pattern).with_context(|| format!("merge pattern {}", r.pattern))?, r.strategy)))
+      .collect::<Result<_, anyhow::Error>>()?;
+    Ok(Self { rules })
+  }
+
+  fn strategy(&self, name: &str) -> MergeStrategy {
+    let options = glob::MatchOptions { case_sensitive: true, require_literal_separator: true, require_literal_leading_dot: false };
+    self.rules.iter().find(|(p, _)| p.matches_with(name, options)).map(|(_, s)| *s).unwrap_or(MergeStrategy::First)
+  }
+
+  /// merge all contents found for `name`, `None` means the entry is dropped
+  fn merge(&self, name: &str, mut contents: Vec<Vec<u8>>) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    Ok(match self.strategy(name) {
+      MergeStrategy::Discard => None,
+      MergeStrategy::First => Some(contents.swap_remove(0)),
+      MergeStrategy::Last => contents.pop(),
+      MergeStrategy::Concat => Some(contents.into_iter().fold(Vec::new(), |mut result, mut content| {
+        if !result.is_empty() && !result.ends_with(b"\n") {
+          result.push(b'\n');
+        }
+        result.append(&mut content);
+        result
+      })),
+      MergeStrategy::Service => {
+        let mut lines = Vec::new();
+        for content in &contents {
+          for line in String::from_utf8_lossy(content).lines().map(|l| l.trim()) {
+            if !line.is_empty() && !line.starts_with('#') && !lines.iter().any(|l| l == line) {
+              lines.push(line.to_string());
+            }
+          }
+        }
+        Some(lines.into_iter().map(|l| l + "\n").collect::<String>().into_bytes())
+      },
+      MergeStrategy::Error => {
+        if contents.iter().any(|c| c != &contents[0]) {
+          return Err(anyhow::Error::msg(format!("conflicting entry {}", name)))
+        }
+        Some(contents.swap_remove(0))
+      },
+    })
+  }
+}
+
+/// rename packages in entry names, service files and class constant pools,
+/// scala signatures are not rewritten so shaded scala libraries are only usable from java
+struct Shader {
+  rules: Vec<(String, String)>,
+}
+impl Shader {
+  fn new(rules: &[ShadeRule]) -> Self {
+    Self { rules: rules.iter().map(|r| (r.from.replace('.', "/") + "/", r.to.replace('.', "/") + "/")).collect() }
+  }
+
+  fn rename(&self, s: &str) -> String {
+    String::from_utf8_lossy(&self.rename_bytes(s.as_bytes())).to_string()
+  }
+
+  /// class files use modified utf-8, so names are replaced as bytes
+  fn rename_bytes(&self, s: &[u8]) -> Vec<u8> {
+    let mut s = s.to_vec();
+    for (from, to) in &self.rules {
+      s = replace_bytes(&s, from.as_bytes(), to.as_bytes());
+      s = replace_bytes(&s, from.replace('/', ".").as_bytes(), to.replace('/', ".").as_bytes());
+    }
+    s
+  }
+
+  fn shade(&self, name: &str, content: Vec<u8>) -> Result<(String, Vec<u8>), anyhow::Error> {
+    if self.rules.is_empty() {
+      return Ok((name.to_string(), content))
+    }
+    let content = if name.ends_with(".class") {
+      self.shade_class(&content).with_context(|| format!("shade {}", name))?
+    } else if name.starts_with("META-INF/services/") {
+      self.rename(&String::from_utf8_lossy(&content)).into_bytes()
+    } else {
+      content
+    };
+    Ok((self.rename(name), content))
+  }
+
+  fn shade_class(&self, class: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let err = || anyhow::Error::msg("malformed class file");
+    let u16_at = |i: usize| class.get(i..i+2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize).ok_or_else(err);
+    let count = u16_at(8)?;
+    let mut result = class[..10].to_vec();
+    let (mut pos, mut index) = (10, 1);
+    while index < count {
+      let tag = *class.get(pos).ok_or_else(err)?;
+      let size = match tag {
+        1 => {
+          let len = u16_at(pos + 1)?;
+          let s = self.rename_bytes(class.get(pos+3..pos+3+len).ok_or_else(err)?);
+          result.push(tag);
+          result.extend_from_slice(&(s.len() as u16).to_be_bytes());
+          result.extend_from_slice(&s);
+          pos += 3 + len;
+          index += 1;
+          continue
+        },
+        3 | 4 => 4,
+        5 | 6 => { index += 1; 8 },
+        7 | 8 | 16 | 19 | 20 => 2,
+        9 | 10 | 11 | 12 | 17 | 18 => 4,
+        15 => 3,
+        _ => return Err(err()),
+      };
+      result.extend_from_slice(class.get(pos..pos+1+size).ok_or_else(err)?);
+      pos += 1 + size;
+      index += 1;
+    }
+    result.extend_from_slice(&class[pos..]);
+    Ok(result)
+  }
+}
+
+fn replace_bytes(s: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+  let mut result = Vec::with_capacity(s.len());
+  let mut i = 0;
+  while i < s.len() {
+    if s[i..].starts_with(from) {
+      result.extend_from_slice(to);
+      i += from.len();
+    } else {
+      result.push(s[i]);
+      i += 1;
+    }
+  }
+  result
+}
+
+fn read_jar(path: &Path, shader: &Shader, entries: &mut BTreeMap<String, Vec<Vec<u8>>>) -> Result<(), anyhow::Error> {
+  let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+  for i in 0..archive.len() {
+    let mut file = archive.by_index(i)?;
+    if file.is_dir() {
+      continue
+    }
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    let (name, content) = shader.shade(file.name(), content)?;
+    entries.entry(name).or_default().push(content);
+  }
+  Ok(())
+}
+
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let assembly = config.package.assembly.clone().unwrap_or_default();
+  let target: build::TargetName = match &assembly.target {
+    Some(target) => target.parse()?,
+    None if Path::new("src/main.scala").exists() => build::TargetName::BinMain,
+    None => build::TargetName::Lib,
+  };
+  let profile = build::Profile::from_release(opts.build.target.release);
+  build::main(opts.build, config).context("build failed")?;
+  let merger = Merger::new(&assembly)?;
+  let shader = Shader::new(&assembly.shade);
+  let mut entries = BTreeMap::new();
+  let library = toolchain::load(config)?.library();
+  for jar_path in Some(build::jar_path(&target, profile, config)).into_iter().chain(resolve::scope_classpath(resolve::Scope::Runtime, config)?).chain(library) {
+    if jar_path.is_dir() {
+      warn!("skip directory in classpath: {}", jar_path.display());
+      continue
+    }
+    read_jar(&jar_path, &shader, &mut entries).with_context(|| format!("read {}", jar_path.display()))?;
+  }
+  let mut manifest = jar::Manifest::default();
+  build::package_manifest(&mut manifest, config);
+  if let Some(main_class) = assembly.main_class.clone().or_else(|| build::main_class(&target, config)) {
+    manifest.set("Main-Class", main_class);
+  }
+  let mut jar = jar::JarBuilder::new(manifest);
+  for (name, contents) in entries {
+    if let Some(content) = merger.merge(&name, contents)? {
+      jar.add(name, content);
+    }
+  }
+  let output = opts.output.unwrap_or_else(|| config.target_dir().join("assembly").join(format!("{}-{}.jar", config.package.name, config.package.version)));
+  let _ = jar.write(&output)?;
+  info!("assembly: {}", output.display());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn merger(rules: &[(&str, MergeStrategy)]) -> Merger {
+    let merge = rules.iter().map(|(pattern, strategy)| MergeRule { pattern: pattern.to_string(), strategy: *strategy }).collect();
+    Merger::new(&Assembly { merge, ..Assembly::default() }).unwrap()
+  }
+
+  fn merge(merger: &Merger, name: &str, contents: &[&str]) -> Option<String> {
+    merger.merge(name, contents.iter().map(|c| c.as_bytes().to_vec()).collect()).unwrap().map(|c| String::from_utf8(c).unwrap())
+  }
+
+  #[test]
+  fn strategies() {
+    use MergeStrategy::*;
+    let merger = merger(&[("first/*", First), ("last/*", Last), ("concat/*", Concat), ("discard/*", Discard), ("error/*", Error)]);
+    assert_eq!(merge(&merger, "first/a", &["1", "2"]).as_deref(), Some("1"));
+    assert_eq!(merge(&merger, "last/a", &["1", "2"]).as_deref(), Some("2"));
+    assert_eq!(merge(&merger, "concat/a", &["a = 1", "b = 2\n", "c = 3"]).as_deref(), Some("a = 1\nb = 2\nc = 3"));
+    assert_eq!(merge(&merger, "discard/a", &["1", "2"]), None);
+    assert_eq!(merge(&merger, "error/a", &["1", "1"]).as_deref(), Some("1"));
+    assert!(merger.merge("error/a", vec![b"1".to_vec(), b"2".to_vec()]).is_err());
+    // not matched by any rule
+    assert_eq!(merge(&merger, "other/a", &["1", "2"]).as_deref(), Some("1"));
+  }
+
+  #[test]
+  fn default_rules() {
+    let merger = merger(&[("reference.conf", MergeStrategy::First)]);
+    assert_eq!(merge(&merger, "reference.conf", &["a", "b"]).as_deref(), Some("a"));
+    assert_eq!(merge(&merger, "META-INF/MANIFEST.MF", &["a"]), None);
+    assert_eq!(merge(&merger, "META-INF/BC.SF", &["a"]), None);
+    assert_eq!(merge(&merger, "a/b/module-info.class", &["a"]), None);
+    // `*` does not cross `/`
+    assert_eq!(merge(&merger, "META-INF/services/a/b", &["x", "y"]).as_deref(), Some("x"));
+  }
+
+  #[test]
+  fn services() {
+    let merger = merger(&[]);
+    let merged = merge(&merger, "META-INF/services/a.Codec", &["# header\na.Json\n\n a.Xml \n", "a.Json\n# other\na.Yaml"]);
+    assert_eq!(merged.as_deref(), Some("a.Json\na.Xml\na.Yaml\n"));
+  }
+
+  #[test]
+  fn replace() {
+    assert_eq!(replace_bytes(b"org/a/B org/a", b"org/a", b"x/org/a"), b"x/org/a/B x/org/a");
+    assert_eq!(replace_bytes(b"aaa", b"aa", b"b"), b"ba");
+    assert_eq!(replace_bytes(b"abc", b"abcd", b"x"), b"abc");
+    assert_eq!(replace_bytes(b"", b"a", b"b"), b"");
+  }
+
+  /// constant pool entries by index with the bytes after the pool,
+  /// the second slot of long and double entries is `None`
+  fn parse_pool(class: &[u8]) -> (Vec<Option<(u8, Vec<u8>)>>, &[u8]) {
+    let u16_at = |i: usize| u16::from_be_bytes([class[i], class[i + 1]]) as usize;
+    let mut pool = vec![None];
+    let mut pos = 10;
+    while pool.len() < u16_at(8) {
+      let tag = class[pos];
+      let size = match tag {
+        1 => 2 + u16_at(pos + 1),
+        3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => 4,
+        5 | 6 => 8,
+        7 | 8 | 16 | 19 | 20 => 2,
+        15 => 3,
+        _ => panic!("unknown tag {} at {}", tag, pos),
+      };
+      pool.push(Some((tag, class[pos + 1..pos + 1 + size].to_vec())));
+      if tag == 5 || tag == 6 {
+        pool.push(None);
+      }
+      pos += 1 + size;
+    }
+    (pool, &class[pos..])
+  }
+
+  fn utf8(pool: &[Option<(u8, Vec<u8>)>], index: usize) -> String {
+    match &pool[index] {
+      Some((1, data)) => String::from_utf8(data[2..].to_vec()).unwrap(),
+      entry => panic!("#{} is not utf8: {:?}", index, entry),
+    }
+  }
+
+  #[test]
+  fn shade_class() {
+    let class = include_bytes!("../tests/data/Shaded.class");
+    let shader = Shader::new(&[ShadeRule { from: "org.example".to_string(), to: "shaded.org.example".to_string() }]);
+    let shaded = shader.shade_class(class).unwrap();
+    assert_eq!(shaded[..10], class[..10]);
+    let (before, before_rest) = parse_pool(class);
+    let (after, after_rest) = parse_pool(&shaded);
+    assert_eq!(before.len(), after.len());
+    // only the pool is rewritten, access flags, fields, methods and attributes are kept
+    assert_eq!(before_rest, after_rest);
+    let class_name = |pool: &[Option<(u8, Vec<u8>)>], index: usize| match &pool[index] {
+      Some((7, data)) => utf8(pool, u16::from_be_bytes([data[0], data[1]]) as usize),
+      entry => panic!("#{} is not a class: {:?}", index, entry),
+    };
+    let this_class = u16::from_be_bytes([after_rest[2], after_rest[3]]) as usize;
+    assert_eq!(class_name(&after, this_class), "shaded/org/example/lib/Shaded");
+    let mut utf8s = Vec::new();
+    for (i, (old, new)) in before.iter().zip(&after).enumerate() {
+      match (old, new) {
+        (Some((1, _)), Some((1, _))) => utf8s.push(utf8(&after, i)),
+        (Some((7, _)), Some((7, _))) => assert!(!class_name(&after, i).starts_with("org/example")),
+        // long, double and references are copied as they are
+        (old, new) => assert_eq!(old, new),
+      }
+    }
+    for name in &["shaded/org/example/lib/Shaded$Helper", "Lshaded/org/example/lib/Shaded$Helper;", "shaded.org.example.lib.Shaded", "java/io/Serializable"] {
+      assert!(utf8s.iter().any(|s| s == name), "{} not found in {:?}", name, utf8s);
+    }
+    assert!(!utf8s.iter().any(|s| s.contains("org/example") && !s.contains("shaded/org/example")));
+  }
+
+  #[test]
+  fn malformed_class() {
+    let shader = Shader::new(&[ShadeRule { from: "a".to_string(), to: "b".to_string() }]);
+    let class = include_bytes!("../tests/data/Shaded.class");
+    assert!(shader.shade_class(&class[..40]).is_err());
+    assert!(shader.shade_class(&class[..9]).is_err());
+  }
+
+  #[test]
+  fn shade_names_and_services() {
+    let shader = Shader::new(&[ShadeRule { from: "org.example".to_string(), to: "shaded.org.example".to_string() }]);
+    let (name, content) = shader.shade("META-INF/services/org.example.Codec", b"org.example.Json\n".to_vec()).unwrap();
+    assert_eq!((name.as_str(), content.as_slice()), ("META-INF/services/shaded.org.example.Codec", &b"shaded.org.example.Json\n"[..]));
+    assert_eq!(shader.shade("org/example/a.txt", b"org.example".to_vec()).unwrap(), ("shaded/org/example/a.txt".to_string(), b"org.example".to_vec()));
+    assert_eq!(shader.shade("org/examples/a.txt", Vec::new()).unwrap().0, "org/examples/a.txt");
+  }
+}
@@ -0,0 +1,371 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use serde_json::{json, Value};
+use crate::{build, ide, job, toolchain};
+use crate::build::{Target, TargetName};
+use crate::config::{PackageConfig, constant::*};
+use crate::utils;
+
+const BSP_VERSION: &str = "2.0.0";
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+}
+
+/// `.bsp/carsier.json`, tells editors how to start the server
+pub fn write_connection_file() -> Result<(), anyhow::Error> {
+  let exe = std::env::current_exe().map(|p| p.display().to_string()).unwrap_or_else(|_| NAME.to_string());
+  let content = json!({
+    "name": NAME,
+    "version": env!("CARGO_PKG_VERSION"),
+    "bspVersion": BSP_VERSION,
+    "languages": ["scala"],
+    "argv": [exe, "bsp"],
+  });
+  let _ = utils::compare_and_write(Path::new(".bsp").join(format!("{}.json", NAME)), serde_json::to_string_pretty(&content)?.as_bytes())?;
+  Ok(())
+}
+
+/// `file://` uri with every byte but unreserved ones and `/` percent-encoded
+fn uri(path: &Path) -> String {
+  let mut result = "file://".to_string();
+  for b in path.to_string_lossy().bytes() {
+    match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => result.push(b as char),
+      _ => result += &format!("%{:02X}", b),
+    }
+  }
+  result
+}
+
+/// a message framed by `Content-Length`, `None` at the end of input
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>, anyhow::Error> {
+  let mut length = None;
+  loop {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      return Ok(None)
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      break
+    }
+    if let Some((key, value)) = line.split_once(':') {
+      if key.trim().eq_ignore_ascii_case("content-length") {
+        length = Some(value.trim().parse::<usize>().context("invalid content length")?);
+      }
+    }
+  }
+  let mut content = vec![0; length.ok_or_else(|| anyhow::Error::msg("missing content length"))?];
+  input.read_exact(&mut content)?;
+  Ok(Some(serde_json::from_slice(&content)?))
+}
+
+fn send<W: Write>(output: &mut W, message: &Value) -> Result<(), anyhow::Error> {
+  let content = serde_json::to_string(message)?;
+  write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+  output.flush()?;
+  Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Diagnostic {
+  path: PathBuf,
+  line: u32,
+  column: u32,
+  severity: u32,
+  message: String,
+}
+
+/// `path:line:column` or `path:line`
+fn parse_position(s: &str) -> Option<(&str, u32, Option<u32>)> {
+  let (rest, last) = s.rsplit_once(':').and_then(|(rest, n)| Some((rest, n.parse::<u32>().ok()?)))?;
+  Some(match rest.rsplit_once(':').and_then(|(path, n)| Some((path, n.parse::<u32>().ok()?))) {
+    Some((path, line)) => (path, line, Some(last)),
+    None => (rest, last, None),
+  })
+}
+
+/// `path:line[:column]: error|warning: message` of scala 2,
+/// without a column it is taken from the caret line below the code
+fn scala2_diagnostic(lines: &[&str], i: usize) -> Option<Diagnostic> {
+  let (head, severity, message) = [(": error: ", 1), (": warning: ", 2)].iter()
+    .find_map(|(kind, severity)| lines[i].split_once(kind).map(|(head, message)| (head, *severity, message)))?;
+  let (path, line, column) = parse_position(head)?;
+  let column = column.unwrap_or_else(|| lines.iter().skip(i + 1).take(3)
+    .find(|l| l.trim() == "^").and_then(|l| l.find('^')).map(|c| c as u32 + 1).unwrap_or(1));
+  Some(Diagnostic { path: path.into(), line, column, severity, message: message.to_string() })
+}
+
+/// `-- [E007] Type Mismatch Error: path:line:column ---` of scala 3, or the position on a `--> path:line:column`
+/// line below, followed by `|` lines of the code, the carets and the message
+fn scala3_diagnostic(lines: &[&str], i: usize) -> Option<Diagnostic> {
+  let header = lines[i].strip_prefix("-- ")?.trim_end_matches('-').trim_end();
+  let header = header.split_once("] ").map(|(_, h)| h).unwrap_or(header);
+  let (title, position) = match header.rsplit_once(": ") {
+    Some((title, position)) if parse_position(position).is_some() => (title, position.to_string()),
+    _ => (header, lines.get(i + 1)?.trim().strip_prefix("--> ")?.to_string()),
+  };
+  let severity = if title.contains("Error") { 1 } else if title.contains("Warning") { 2 } else { 3 };
+  let (path, line, column) = parse_position(&position)?;
+  let message = lines.iter().skip(i + 1).take_while(|l| !l.starts_with("-- ") && !l.trim().is_empty())
+    .filter_map(|l| l.split_once('|').filter(|(number, _)| number.trim().is_empty()).map(|(_, text)| text.trim()))
+    .filter(|text| !text.is_empty() && !text.chars().all(|c| c == '^'))
+    .collect::<Vec<_>>().join("\n");
+  Some(Diagnostic {
+    path: path.into(), line, column: column.unwrap_or(1), severity,
+    message: if message.is_empty() { title.to_string() } else { message },
+  })
+}
+
+/// diagnostics of scala 2 and scala 3 compiler output
+fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+  let lines = output.lines().collect::<Vec<_>>();
+  (0..lines.len()).filter_map(|i| scala3_diagnostic(&lines, i).or_else(|| scala2_diagnostic(&lines, i))).collect()
+}
+
+struct Server<'a> {
+  opts: build::Opts,
+  config: &'a PackageConfig,
+  workspace: PathBuf,
+  /// files with published diagnostics, cleared on the next compile
+  reported: BTreeSet<PathBuf>,
+}
+
+impl<'a> Server<'a> {
+  fn target_id(&self, name: &TargetName) -> Value {
+    json!({ "uri": format!("{}?id={}", uri(&self.workspace), name.to_string()) })
+  }
+
+  /// targets in `params.targets`, all of them if not given
+  fn targets(&self, params: &Value) -> Result<Vec<Target>, anyhow::Error> {
+    let all = build::get_target(&self.opts, self.config)?;
+    let selected = match params["targets"].as_array() {
+      Some(ids) => ids.iter().filter_map(|id| id["uri"].as_str()?.rsplit_once("?id=").map(|(_, n)| n.to_string())).collect::<Vec<_>>(),
+      None => return Ok(all),
+    };
+    Ok(all.into_iter().filter(|t| selected.contains(&t.name.to_string())).collect())
+  }
+
+  /// resolve, preprocess and write plugin options, sources and classpath come from these
+  fn prepare(&self) -> Result<(), anyhow::Error> {
+    let mut scheduler = job::Scheduler::new();
+    build::prepare_jobs(&mut scheduler, self.opts.resolve.clone(), self.opts.preprocess.clone(), self.config);
+    scheduler.run(build::parallel_jobs(self.opts.jobs))
+  }
+
+  fn build_targets(&self) -> Result<Value, anyhow::Error> {
+    let (version, jars) = toolchain::resolve(&self.opts.resolve.coursier, self.config)?;
+    let targets = build::get_target(&self.opts, self.config)?.iter().map(|target| json!({
+      "id": self.target_id(&target.name),
+      "displayName": format!("{}-{}", self.config.package.name, target.name.to_string()),
+      "baseDirectory": uri(&self.workspace),
+      "tags": [if build::main_class(&target.name, self.config).is_some() { "application" } else { "library" }],
+      "languageIds": ["scala"],
+      "dependencies": [],
+      "capabilities": { "canCompile": true, "canTest": false, "canRun": false, "canDebug": false },
+      "dataKind": "scala",
+      "data": {
+        "scalaOrganization": "org.scala-lang",
+        "scalaVersion": version,
+        "scalaBinaryVersion": crate::config::binary_version(&self.config.package.edition),
+        "platform": 1,
+        "jars": jars.iter().map(|j| uri(j)).collect::<Vec<_>>(),
+      },
+    })).collect::<Vec<_>>();
+    Ok(json!({ "targets": targets }))
+  }
+
+  fn sources(&self, params: &Value) -> Result<Value, anyhow::Error> {
+    let items = self.targets(params)?.iter().map(|target| Ok(json!({
+      "target": self.target_id(&target.name),
+      "sources": build::target_sources(target, self.config)?.iter().map(|f| json!({
+        "uri": uri(&self.workspace.join(f)), "kind": 1, "generated": false,
+      })).collect::<Vec<_>>(),
+    }))).collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok(json!({ "items": items }))
+  }
+
+  fn scalac_options(&self, params: &Value) -> Result<Value, anyhow::Error> {
+    let options = ide::scalac_options(&self.workspace, self.config)?;
+    let (_, jars) = toolchain::resolve(&self.opts.resolve.coursier, self.config)?;
+    let items = self.targets(params)?.iter().map(|target| Ok(json!({
+      "target": self.target_id(&target.name),
+      "options": options,
+      "classpath": ide::classpath(target, &self.workspace, &jars, self.config)?.iter().map(|p| uri(p)).collect::<Vec<_>>(),
+      "classDirectory": uri(&self.workspace.join(build::classes_dir(&target.name, target.profile, self.config))),
+    }))).collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok(json!({ "items": items }))
+  }
+
+  /// run the build pipeline on the requested targets, then publish what scalac reported
+  fn compile<W: Write>(&mut self, params: &Value, output: &mut W) -> Result<Value, anyhow::Error> {
+    let targets = self.targets(params)?;
+    let mut opts = self.opts.clone();
+    opts.target.targets = targets.iter().map(|t| t.name.to_string()).collect();
+    let result = build::main(opts, self.config);
+    if let Err(e) = &result {
+      warn!("{:#}", e);
+    }
+    // targets not compiled this time keep the log of their last compile
+    let mut diagnostics = Vec::new();
+    for target in &targets {
+      let log = build::compile_log(&target.name, target.profile, self.config);
+      diagnostics.extend(parse_diagnostics(&utils::load_content(log)?.unwrap_or_default()));
+    }
+    let mut by_file = BTreeMap::<PathBuf, Vec<Value>>::new();
+    for d in diagnostics {
+      let position = json!({ "line": d.line.saturating_sub(1), "character": d.column.saturating_sub(1) });
+      by_file.entry(self.workspace.join(&d.path)).or_default().push(json!({
+        "range": { "start": position, "end": position },
+        "severity": d.severity,
+        "source": "scalac",
+        "message": d.message,
+      }));
+    }
+    for path in std::mem::take(&mut self.reported) {
+      by_file.entry(path).or_default();
+    }
+    let build_target = targets.first().map(|t| self.target_id(&t.name)).unwrap_or(Value::Null);
+    for (path, diagnostics) in by_file {
+      if !diagnostics.is_empty() {
+        self.reported.insert(path.clone());
+      }
+      send(output, &json!({
+        "jsonrpc": "2.0",
+        "method": "build/publishDiagnostics",
+        "params": {
+          "textDocument": { "uri": uri(&path) },
+          "buildTarget": build_target,
+          "originId": params["originId"],
+          "diagnostics": diagnostics,
+          "reset": true,
+        },
+      }))?;
+    }
+    Ok(json!({ "originId": params["originId"], "statusCode": if result.is_ok() { 1 } else { 2 } }))
+  }
+
+  /// `Ok(None)` for requests without a result
+  fn handle<W: Write>(&mut self, method: &str, params: &Value, output: &mut W) -> Result<Option<Value>, anyhow::Error> {
+    Ok(Some(match method {
+      "build/initialize" => {
+        self.prepare().context("prepare failed")?;
+        json!({
+          "displayName": NAME,
+          "version": env!("CARGO_PKG_VERSION"),
+          "bspVersion": BSP_VERSION,
+          "capabilities": {
+            "compileProvider": { "languageIds": ["scala"] },
+            "dependencySourcesProvider": false,
+            "canReload": true,
+          },
+        })
+      },
+      "workspace/buildTargets" => self.build_targets()?,
+      "workspace/reload" => {
+        self.prepare().context("prepare failed")?;
+        Value::Null
+      },
+      "buildTarget/sources" => self.sources(params)?,
+      "buildTarget/scalacOptions" => self.scalac_options(params)?,
+      "buildTarget/compile" => self.compile(params, output)?,
+      "build/shutdown" => Value::Null,
+      _ => return Ok(None),
+    }))
+  }
+}
+
+/// serve the build server protocol on stdin and stdout until `build/exit`
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let mut server = Server { opts: opts.build, config, workspace: std::env::current_dir()?, reported: BTreeSet::new() };
+  let stdin = std::io::stdin();
+  let mut input = stdin.lock();
+  let mut output = std::io::stdout();
+  while let Some(message) = read_message(&mut input)? {
+    let method = message["method"].as_str().unwrap_or_default();
+    debug!("bsp: {}", method);
+    if method == "build/exit" {
+      break
+    }
+    let result = server.handle(method, &message["params"], &mut output);
+    let id = match message.get("id") {
+      Some(id) => id,
+      None => continue,
+    };
+    let response = match result {
+      Ok(Some(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+      Ok(None) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": format!("method not found: {}", method) } }),
+      Err(e) => {
+        error!("{}: {:#}", method, e);
+        json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32603, "message": format!("{:#}", e) } })
+      },
+    };
+    send(&mut output, &response)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_diagnostics, uri};
+  use std::path::Path;
+
+  #[test]
+  fn encoded_uri() {
+    assert_eq!(uri(Path::new("/a/b-c_d.scala")), "file:///a/b-c_d.scala");
+    assert_eq!(uri(Path::new("/my project/#1/é.scala")), "file:///my%20project/%231/%C3%A9.scala");
+  }
+
+  #[test]
+  fn diagnostics() {
+    let output = concat!(
+      "target/src/a.scala:3:5: error: not found: value x\n",
+      "  x + 1\n",
+      "  ^\n",
+      "target/src/b.scala:7: warning: match may not be exhaustive.\n",
+      "  s match {\n",
+      "    ^\n",
+      "1 warning\n",
+      "1 error\n");
+    let diagnostics = parse_diagnostics(output);
+    assert_eq!(diagnostics.len(), 2);
+    let (a, b) = (&diagnostics[0], &diagnostics[1]);
+    assert_eq!((a.path.as_path(), a.line, a.column, a.severity, a.message.as_str()), (Path::new("target/src/a.scala"), 3, 5, 1, "not found: value x"));
+    assert_eq!((b.path.as_path(), b.line, b.column, b.severity), (Path::new("target/src/b.scala"), 7, 5, 2));
+  }
+
+  #[test]
+  fn scala3_diagnostics() {
+    let output = concat!(
+      "-- [E007] Type Mismatch Error: target/src/a.scala:3:15 ------------------------\n",
+      "3 |  val x: Int = \"a\"\n",
+      "  |               ^^^\n",
+      "  |               Found:    (\"a\" : String)\n",
+      "  |               Required: Int\n",
+      "  |\n",
+      "  | longer explanation available when compiling with `-explain`\n",
+      "-- Warning: target/src/b.scala:7:4 ---------------------------------------------\n",
+      "7 |    s match {\n",
+      "  |    ^\n",
+      "  |    match may not be exhaustive.\n",
+      "-- [E006] Not Found Error -------------------------------------------------------\n",
+      "  --> target/src/c.scala:2:9\n",
+      "2 |  val y = z\n",
+      "  |          ^\n",
+      "  |          Not found: z\n",
+      "1 warning found\n",
+      "2 errors found\n");
+    let diagnostics = parse_diagnostics(output);
+    assert_eq!(diagnostics.len(), 3);
+    let (a, b, c) = (&diagnostics[0], &diagnostics[1], &diagnostics[2]);
+    assert_eq!((a.path.as_path(), a.line, a.column, a.severity), (Path::new("target/src/a.scala"), 3, 15, 1));
+    assert_eq!(a.message, "Found:    (\"a\" : String)\nRequired: Int\nlonger explanation available when compiling with `-explain`");
+    assert_eq!((b.path.as_path(), b.line, b.column, b.severity, b.message.as_str()), (Path::new("target/src/b.scala"), 7, 4, 2, "match may not be exhaustive."));
+    assert_eq!((c.path.as_path(), c.line, c.column, c.severity, c.message.as_str()), (Path::new("target/src/c.scala"), 2, 9, 1, "Not found: z"));
+  }
+}
@@ -1,14 +1,14 @@
 #![allow(dead_code)]
 
-use std::rc::Rc;
-use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 use anyhow::Context;
-use crate::{resolve, preprocess};
+use crate::{resolve, preprocess, server, job, jar, toolchain, incremental};
 use crate::utils;
-use crate::config::{PackageConfig, Resource, constant::*};
+use crate::config::{PackageConfig, FeatureLike, constant::*};
 
-#[derive(Clap)]
+#[derive(Clap, Clone)]
 pub struct Opts {
   #[clap(flatten)]
   pub target: TargetOpts,
@@ -16,12 +16,22 @@ pub struct Opts {
   pub preprocess: preprocess::Opts,
   #[clap(flatten)]
   pub resolve: resolve::Opts,
+  /// number of parallel jobs, defaults to number of cpus
+  #[clap(short = "j", long)]
+  pub jobs: Option<usize>,
 }
 
-#[derive(Clap)]
+#[derive(Clap, Clone)]
 pub struct TargetOpts {
+  /// build with release profile into `target/release`
   #[clap(long)]
   pub release: bool,
+  /// compile with a long-lived compile server, which keeps the compiler warm between builds
+  #[clap(long)]
+  pub server: bool,
+  /// only build these targets, e.g. `lib` or `main`
+  #[clap(long = "target")]
+  pub targets: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,12 +49,37 @@ impl ToString for TargetName {
     }
   }
 }
+impl std::str::FromStr for TargetName {
+  type Err = anyhow::Error;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s {
+      "lib" => TargetName::Lib,
+      "main" => TargetName::BinMain,
+      s if s.starts_with("bin_") => TargetName::Bin(s["bin_".len()..].to_string()),
+      s if s.starts_with("example_") => TargetName::Example(s["example_".len()..].to_string()),
+      s if s.starts_with("test_") => TargetName::Test(s["test_".len()..].to_string()),
+      s => return Err(anyhow::Error::msg(format!("unknown target {}", s))),
+    })
+  }
+}
 #[derive(Debug, Clone, Copy)]
 pub enum Profile {
   Debug, Release, RelWithDebugInfo, Test
 }
+impl Profile {
+  pub fn from_release(release: bool) -> Self {
+    if release { Profile::Release } else { Profile::Debug }
+  }
+  /// where jars and classes of this profile go
+  pub fn build_dir(self, config: &PackageConfig) -> std::path::PathBuf {
+    match self {
+      Profile::Debug | Profile::Test => config.target_dir().join("build"),
+      Profile::Release | Profile::RelWithDebugInfo => config.target_dir().join("release"),
+    }
+  }
+}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FeatureFlag {
   /// features in group could only select one
   Conflict,
@@ -58,17 +93,103 @@ pub enum FeatureFlag {
 #[derive(Debug, Clone)]
 pub struct Feature {
   name: String,
-  group: Vec<Rc<Feature>>,
+  group: Vec<Arc<Feature>>,
   flag: FeatureFlag,
 }
 
+#[derive(Debug, Clone)]
 pub struct Target {
   pub name: TargetName,
   pub profile: Profile,
-  pub features: BTreeMap<String, Rc<Feature>>,
+  pub features: BTreeMap<String, Arc<Feature>>,
+}
+
+pub fn get_target(opts: &Opts, config: &PackageConfig) -> Result<Vec<Target>, anyhow::Error> {
+  find_targets(&opts.target.targets, Profile::from_release(opts.target.release), &opts.resolve.features, config)
+}
+
+/// `edition2_12` for edition `2.12`, so files like `foo-edition2_12.scala` are only built for it
+pub fn edition_features(config: &PackageConfig) -> BTreeMap<String, Arc<Feature>> {
+  let name = format!("edition{}", crate::config::binary_version(&config.package.edition).replace('.', "_"));
+  let feature = Arc::new(Feature { name: name.clone(), group: Vec::new(), flag: FeatureFlag::Virtual });
+  vec![(name, feature)].into_iter().collect()
+}
+
+/// enable `name` and, through `[features]`, everything in its group
+fn enable_feature(name: &str, config: &PackageConfig, result: &mut BTreeMap<String, Arc<Feature>>, visiting: &mut Vec<String>) -> Result<Arc<Feature>, anyhow::Error> {
+  if let Some(feature) = result.get(name) {
+    return Ok(feature.clone())
+  }
+  if visiting.iter().any(|n| n == name) {
+    return Err(anyhow::Error::msg(format!("feature {} includes itself: {}", name, visiting.join(" -> "))))
+  }
+  let (members, flag) = match config.features.get(name) {
+    Some(FeatureLike::Group(group)) => (group.as_slice(), FeatureFlag::Set),
+    Some(FeatureLike::Full(feature)) if feature.computed || feature.conflict =>
+      return Err(anyhow::Error::msg(format!("feature {} is only enabled by its group", name))),
+    Some(FeatureLike::Full(feature)) => (feature.group.as_slice(), FeatureFlag::Set),
+    None if config.dependencies.contains_key(name) => (&[][..], FeatureFlag::Package),
+    None => (&[][..], FeatureFlag::Set),
+  };
+  visiting.push(name.to_string());
+  let group = members.iter().map(|member| enable_feature(member, config, result, visiting)).collect::<Result<Vec<_>, _>>()?;
+  visiting.pop();
+  let feature = Arc::new(Feature { name: name.to_string(), group, flag });
+  result.insert(name.to_string(), feature.clone());
+  Ok(feature)
 }
 
-fn get_target(opts: &Opts, _config: &PackageConfig) -> Result<Vec<Target>, anyhow::Error> {
+/// the edition feature and features enabled by `--features` with their groups,
+/// then every virtual or conflict feature one of whose group is enabled
+pub fn target_features(features: &[String], config: &PackageConfig) -> Result<BTreeMap<String, Arc<Feature>>, anyhow::Error> {
+  let mut result = edition_features(config);
+  for name in features {
+    enable_feature(name, config, &mut result, &mut Vec::new())?;
+  }
+  loop {
+    let computed = config.features.iter().filter_map(|(name, feature)| match feature {
+      FeatureLike::Full(feature) if (feature.computed || feature.conflict) && !result.contains_key(name) => {
+        let group = feature.group.iter().filter_map(|member| result.get(member).cloned()).collect::<Vec<_>>();
+        if group.is_empty() {
+          return None
+        }
+        let flag = if feature.conflict { FeatureFlag::Conflict } else { FeatureFlag::Virtual };
+        Some(Arc::new(Feature { name: name.clone(), group, flag }))
+      },
+      _ => None,
+    }).collect::<Vec<_>>();
+    if computed.is_empty() {
+      break
+    }
+    for feature in computed {
+      if feature.flag == FeatureFlag::Conflict && feature.group.len() > 1 {
+        let names = feature.group.iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        return Err(anyhow::Error::msg(format!("features {} of {} conflict", names.join(", "), feature.name)))
+      }
+      result.insert(feature.name.clone(), feature);
+    }
+  }
+  Ok(result)
+}
+
+/// what `[target.<feature-expr>]` is evaluated against: the features of the target, see `target_features`,
+/// the os and its family like `linux` and `unix`, and pairs like `os=linux` for `os = "linux"`
+pub fn cfg_features(config: &PackageConfig, features: &BTreeMap<String, Arc<Feature>>) -> BTreeSet<String> {
+  let mut result = features.keys().cloned().collect::<BTreeSet<_>>();
+  for value in &[std::env::consts::OS, std::env::consts::FAMILY] {
+    result.insert(value.to_string());
+  }
+  let pairs = vec![
+    ("os", std::env::consts::OS.to_string()),
+    ("family", std::env::consts::FAMILY.to_string()),
+    ("edition", crate::config::binary_version(&config.package.edition)),
+  ];
+  result.extend(pairs.into_iter().map(|(key, value)| format!("{}={}", key, value)));
+  result
+}
+
+/// targets found under `src`, only those in `filter` if it is not empty
+pub fn find_targets(filter: &[String], profile: Profile, features: &[String], config: &PackageConfig) -> Result<Vec<Target>, anyhow::Error> {
   let mut names = Vec::new();
   if Path::new("src/lib.scala").exists() {
     names.push(TargetName::Lib)
@@ -76,58 +197,425 @@ fn get_target(opts: &Opts, _config: &PackageConfig) -> Result<Vec<Target>, anyho
   if Path::new("src/main.scala").exists() {
     names.push(TargetName::BinMain)
   }
+  if !filter.is_empty() {
+    let selected = filter.iter().map(|t| t.parse()).collect::<Result<Vec<TargetName>, _>>()?;
+    names.retain(|n| selected.iter().any(|s| s.to_string() == n.to_string()));
+  }
   if names.is_empty() {
     return Err(anyhow::Error::msg("no target found"))
   }
+  let features = target_features(features, config)?;
   let targets = names.into_iter().map(|name| Target {
     name,
-    profile: if opts.target.release { Profile::Release } else { Profile::Debug },
-    features: BTreeMap::new()
+    profile,
+    features: features.clone(),
   }).collect();
   Ok(targets)
 }
 
-fn compile(target: Target, cp: &str, files: &str) -> Result<std::path::PathBuf, anyhow::Error> {
-  let target_name = target.name.to_string();
-  let target = target_dir().join("build").join(&target_name).with_extension("jar");
-  std::fs::create_dir_all(target.parent().unwrap())?;
-  let opts = vec![
-    "--class-path", cp,
-    "--source-path", "src",
-    "@target/plugin_opts"
+/// dev dependencies are only on the classpath of tests, examples and the test profile
+pub fn dependency_scope(target: &Target) -> resolve::Scope {
+  match (&target.name, target.profile) {
+    (TargetName::Test(_), _) | (TargetName::Example(_), _) | (_, Profile::Test) => resolve::Scope::Dev,
+    _ => resolve::Scope::Compile,
+  }
+}
+
+/// `@file` argument of scalac reading `name` under the target dir
+pub fn args_file(name: &str, config: &PackageConfig) -> String {
+  format!("@{}", config.target_dir().join(name).display())
+}
+
+pub fn classes_dir(target: &TargetName, profile: Profile, config: &PackageConfig) -> std::path::PathBuf {
+  profile.build_dir(config).join("classes").join(target.to_string())
+}
+
+/// scala 3 compilers can't load the nsc module plugin,
+/// so they compile the preprocessed copies under the target dir instead
+pub fn uses_plugin(config: &PackageConfig) -> bool {
+  crate::config::binary_version(&config.package.edition) != "3"
+}
+
+/// where the compiler looks up sources not given on the command line
+pub fn source_path(config: &PackageConfig) -> std::path::PathBuf {
+  if uses_plugin(config) { Path::new("src").to_owned() } else { config.target_dir().join("src") }
+}
+
+/// compiler arguments with the classpath of `scope` and the scala library, `files` and `extra`,
+/// `classes` of an earlier compile go first on the classpath
+fn scalac_args(toolchain: &toolchain::Toolchain, config: &PackageConfig, scope: resolve::Scope, files: &str, extra: &[&std::ffi::OsStr], classes: Option<&Path>) -> Result<Vec<std::ffi::OsString>, anyhow::Error> {
+  let cp = std::env::join_paths(classes.map(Path::to_owned).into_iter().chain(resolve::scope_classpath(scope, config)?).chain(toolchain.library()))?;
+  let mut args: Vec<std::ffi::OsString> = vec![
+    "-classpath".into(), cp,
+    "-sourcepath".into(), source_path(config).into(),
+    args_file("plugin_opts", config).into(),
+    files.into(),
   ];
-  utils::call("scalac", opts.into_iter().map(std::ffi::OsStr::new).chain(vec![
-    // "--dependency-file".as_ref(), target_dir().join("scala_dep").as_ref(),
-    files.as_ref(),
-    "-d".as_ref(), target.as_os_str(),
-  ].into_iter()))?;
-  info!("compiled: {} => {}", files, target_name);
-  Ok(target)
-}
-
-fn package(target: &Path, resources: &Vec<Resource>) -> Result<(), anyhow::Error> {
-  let resource_files = resources.iter().map(|r| glob::glob(&r.include)).collect::<Result<Vec<_>, _>>()?
-    .into_iter().flat_map(|g| g.into_iter()).filter_map(|i| i.ok()).collect::<Vec<_>>();
-  if resource_files.is_empty() {
-    return Ok(())
-  }
-  let resource_str = resource_files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join("\n");
-  let _ = utils::compare_and_write(target_dir().join("resources.txt"), resource_str.as_bytes())?;
-  utils::call("jar", vec!["--update".as_ref(), "--file".as_ref(), target.as_os_str(), "@target/resources.txt".as_ref()].into_iter())?;
+  args.extend(extra.iter().map(|&a| a.to_owned()));
+  Ok(args)
+}
+
+/// run the compiler of `target/toolchain.json` with the classpath of `scope` and the scala library
+fn scalac(config: &PackageConfig, scope: resolve::Scope, files: &str, extra: &[&std::ffi::OsStr]) -> Result<String, anyhow::Error> {
+  let toolchain = toolchain::load(config)?;
+  utils::call_output(&mut toolchain.scalac_command(scalac_args(&toolchain, config, scope, files, extra, None)?)?)
+}
+
+/// the same as `scalac`, but in the warm compiler of the compile server,
+/// with the earlier `classes` on the classpath and the analysis written to `analysis`
+fn scalac_server(config: &PackageConfig, scope: resolve::Scope, files: &str, extra: &[&std::ffi::OsStr], classes: &Path, analysis: &Path) -> Result<String, anyhow::Error> {
+  let toolchain = toolchain::load(config)?;
+  let args = toolchain.options.iter().map(std::ffi::OsString::from).chain(scalac_args(&toolchain, config, scope, files, extra, Some(classes))?)
+    .map(|a| a.to_string_lossy().to_string()).collect::<Vec<_>>();
+  server::compile(analysis, &args, config)
+}
+
+/// what scalac printed on the last compile of a target, read by `carsier bsp`
+pub fn compile_log(target: &TargetName, profile: Profile, config: &PackageConfig) -> std::path::PathBuf {
+  profile.build_dir(config).join(target.to_string()).with_extension("log")
+}
+
+/// run scalac, in the compile server if `analysis` is given, keeping what it printed in `compile_log`
+fn run_scalac(target: &Target, files: &str, classes: &Path, analysis: Option<&Path>, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let log = compile_log(&target.name, target.profile, config);
+  let _ = std::fs::remove_file(&log);
+  let extra = ["-d".as_ref(), classes.as_os_str()];
+  let result = match analysis {
+    Some(analysis) => scalac_server(config, dependency_scope(target), files, &extra, classes, analysis),
+    None => scalac(config, dependency_scope(target), files, &extra),
+  };
+  let output = match &result {
+    Ok(output) => output.clone(),
+    Err(e) => e.downcast_ref::<utils::CallError>().map(|e| e.output())
+      .or_else(|| e.downcast_ref::<server::CompileError>().map(|e| e.0.clone()))
+      .unwrap_or_default(),
+  };
+  let _ = utils::compare_and_write(&log, output.as_bytes())?;
+  let output = result?;
+  if analysis.is_some() && !output.trim().is_empty() {
+    info!("{}", output.trim_end());
+  }
   Ok(())
 }
 
+/// compile every file into a cleared classes dir, so no stale classes are left behind
+fn compile(target: Target, files: &str, config: &PackageConfig) -> Result<std::path::PathBuf, anyhow::Error> {
+  let classes = classes_dir(&target.name, target.profile, config);
+  let _ = std::fs::remove_dir_all(&classes);
+  std::fs::create_dir_all(&classes)?;
+  run_scalac(&target, files, &classes, None, config)?;
+  info!("compiled: {} => {}", files, target.name.to_string());
+  Ok(classes)
+}
+
+/// hash of the classpath, plugin options and compiler a target is compiled with
+fn environment_fingerprint(scope: resolve::Scope, config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let target_dir = config.target_dir();
+  let mut result = String::new();
+  for path in &[scope.file("classpath", config), target_dir.join("plugin_opts"), target_dir.join("toolchain.json")] {
+    let content = utils::load_content_raw(path)?.unwrap_or_default();
+    result += &format!("{} {:016x}\n", path.display(), utils::hash_content(&content));
+  }
+  Ok(result)
+}
+
+/// hash of each source of a units file, by canonical path
+fn source_hashes(units_file: &str, config: &PackageConfig) -> Result<BTreeMap<std::path::PathBuf, String>, anyhow::Error> {
+  let files = utils::load_content(config.target_dir().join("src_files").join(units_file))?.unwrap_or_default();
+  let mut result = BTreeMap::new();
+  for path in files.lines().map(std::path::PathBuf::from) {
+    let content = utils::load_content_raw(&path)?.unwrap_or_default();
+    result.insert(std::fs::canonicalize(&path).unwrap_or(path), format!("{:016x}", utils::hash_content(&content)));
+  }
+  Ok(result)
+}
+
+/// hash of the sources, classpath, plugin options and compiler a target is compiled from
+fn sources_fingerprint(scope: resolve::Scope, units_file: &str, config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let mut result = environment_fingerprint(scope, config)?;
+  for (path, hash) in source_hashes(units_file, config)? {
+    result += &format!("{} {}\n", path.display(), hash);
+  }
+  Ok(result)
+}
+
+/// compile unless the classes are already built from the same fingerprint,
+/// which is only written after a successful compile
+fn compile_dirty(target: Target, units_file: &str, config: &PackageConfig, server: bool) -> Result<std::path::PathBuf, anyhow::Error> {
+  if server {
+    return compile_incremental(target, units_file, config)
+  }
+  let classes = classes_dir(&target.name, target.profile, config);
+  let fingerprint_file = classes.with_extension("fingerprint");
+  // the classes are no longer the ones an analysis of the compile server describes
+  let _ = std::fs::remove_file(classes.with_extension("analysis"));
+  let fingerprint = sources_fingerprint(dependency_scope(&target), units_file, config)?;
+  if classes.exists() && utils::load_content(&fingerprint_file)?.as_deref() == Some(fingerprint.as_str()) {
+    info!("fresh: {}", target.name.to_string());
+    return Ok(classes)
+  }
+  let _ = std::fs::remove_file(&fingerprint_file);
+  let classes = compile(target, &args_file(&format!("src_files/{}", units_file), config), config)?;
+  let _ = utils::compare_and_write(&fingerprint_file, fingerprint.as_bytes())?;
+  Ok(classes)
+}
+
+/// compile in the compile server only the changed sources and the sources using classes they define,
+/// everything if there is no analysis of the last compile with the same classpath and compiler,
+/// a server not answering in time is killed and the target compiled without it
+fn compile_incremental(target: Target, units_file: &str, config: &PackageConfig) -> Result<std::path::PathBuf, anyhow::Error> {
+  let target_name = target.name.to_string();
+  let classes = classes_dir(&target.name, target.profile, config);
+  let analysis_file = classes.with_extension("analysis");
+  let report = classes.with_extension("deps");
+  let environment = environment_fingerprint(dependency_scope(&target), config)?;
+  let hashes = source_hashes(units_file, config)?;
+  // the fingerprint of a compile without the server no longer holds after this one
+  let _ = std::fs::remove_file(classes.with_extension("fingerprint"));
+  let previous = incremental::Analysis::load(&analysis_file)?.filter(|a| a.environment == environment && classes.exists());
+  let _ = std::fs::remove_file(&analysis_file);
+  let (analysis, sources) = match previous {
+    Some(analysis) => {
+      let invalidation = analysis.invalidate(&hashes);
+      if invalidation.is_empty() {
+        analysis.save(&analysis_file)?;
+        info!("fresh: {}", target_name);
+        return Ok(classes)
+      }
+      analysis.remove_classes(&classes, invalidation.compile.iter().chain(&invalidation.removed))?;
+      (analysis, invalidation.compile)
+    },
+    None => {
+      let _ = std::fs::remove_dir_all(&classes);
+      (incremental::Analysis::new(environment), hashes.keys().cloned().collect::<BTreeSet<_>>())
+    },
+  };
+  std::fs::create_dir_all(&classes)?;
+  let files_name = format!("src_files/{}.incremental", units_file);
+  let content = sources.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join("\n");
+  let _ = utils::compare_and_write(config.target_dir().join(&files_name), content.as_bytes())?;
+  let files = args_file(&files_name, config);
+  match run_scalac(&target, &files, &classes, Some(&report), config) {
+    Err(e) if e.is::<server::Timeout>() => {
+      warn!("{:#}, compiling {} without it", e, target_name);
+      server::kill(config);
+      return compile(target, &args_file(&format!("src_files/{}", units_file), config), config)
+    },
+    result => result?,
+  }
+  match analysis.update(&hashes, &sources, &utils::load_content(&report)?.unwrap_or_default()) {
+    Some(analysis) => analysis.save(&analysis_file)?,
+    None => warn!("compile server reported no analysis of {}, compiling it all next time", target_name),
+  }
+  info!("compiled: {} of {} sources => {}", sources.len(), hashes.len(), target_name);
+  Ok(classes)
+}
+
+/// type check only, no classes or jars would be written
+fn typecheck(target: Target, files: &str, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  scalac(config, dependency_scope(&target), files, &["-Ystop-after:typer".as_ref()])?;
+  info!("checked: {} => {}", files, target.name.to_string());
+  Ok(())
+}
+
+pub fn main_class(target: &TargetName, config: &PackageConfig) -> Option<String> {
+  match target {
+    TargetName::BinMain => Some(format!("{}.{}.Main", config.package.registry, config.package.name)),
+    _ => None,
+  }
+}
+
+/// `Implementation-*` entries from `[package]`
+pub fn package_manifest(manifest: &mut jar::Manifest, config: &PackageConfig) {
+  manifest.set("Implementation-Title", config.package.name.as_str());
+  manifest.set("Implementation-Version", config.package.version.as_str());
+  if let Some(org) = &config.package.org {
+    manifest.set("Implementation-Vendor", org.as_str());
+  }
+  if let Some(homepage) = &config.package.homepage {
+    manifest.set("Implementation-URL", homepage.as_str());
+  }
+  if !config.package.keywords.is_empty() {
+    manifest.set("Carsier-Keywords", config.package.keywords.join(","));
+  }
+}
+
+pub fn jar_path(target: &TargetName, profile: Profile, config: &PackageConfig) -> std::path::PathBuf {
+  profile.build_dir(config).join(target.to_string()).with_extension("jar")
+}
+
+/// write `target/build/<target>.jar` from compiled classes and resources,
+/// skipped if the fingerprint of all entries is unchanged
+fn package(target: &Target, classes: &Path, config: &PackageConfig) -> Result<std::path::PathBuf, anyhow::Error> {
+  let jar_path = jar_path(&target.name, target.profile, config);
+  let mut manifest = jar::Manifest::default();
+  package_manifest(&mut manifest, config);
+  if let Some(main_class) = main_class(&target.name, config) {
+    manifest.set("Main-Class", main_class);
+  }
+  let mut jar = jar::JarBuilder::new(manifest);
+  jar.add_dir(classes, "")?;
+  add_resources(&mut jar, target, config)?;
+  let fingerprint_file = jar_path.with_extension("fingerprint");
+  let fingerprint = jar.fingerprint();
+  if jar_path.exists() && utils::load_content(&fingerprint_file)?.as_deref() == Some(fingerprint.as_str()) {
+    debug!("fresh: {}", jar_path.display());
+    return Ok(jar_path)
+  }
+  // written only after the jar, so a failed write is never taken as fresh
+  let _ = std::fs::remove_file(&fingerprint_file);
+  let _ = jar.write(&jar_path)?;
+  let _ = utils::compare_and_write(&fingerprint_file, fingerprint.as_bytes())?;
+  info!("packaged: {}", jar_path.display());
+  Ok(jar_path)
+}
+
+fn add_resources(jar: &mut jar::JarBuilder, target: &Target, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let target_name = target.name.to_string();
+  let vars = vec![
+    ("package.name", config.package.name.clone()),
+    ("package.version", config.package.version.clone()),
+    ("package.edition", config.package.edition.clone()),
+    ("package.registry", config.package.registry.clone()),
+    ("target.name", target_name.clone()),
+  ].into_iter().collect::<BTreeMap<_, _>>();
+  for resource in &config.resources {
+    if !resource.targets.is_empty() && !resource.targets.contains(&target_name) {
+      continue
+    }
+    if !resource.features.is_empty() && !resource.features.iter().any(|f| target.features.contains_key(f)) {
+      continue
+    }
+    let excludes = resource.exclude.iter().map(|e| glob::Pattern::new(e)).collect::<Result<Vec<_>, _>>()?;
+    let prefix = resource.prefix.as_deref().unwrap_or("");
+    for path in glob::glob(&resource.include)?.filter_map(|i| i.ok()).filter(|p| p.is_file()) {
+      if excludes.iter().any(|e| e.matches_path(&path)) {
+        continue
+      }
+      let relative = match &resource.strip_prefix {
+        Some(strip) => path.strip_prefix(strip).unwrap_or(&path),
+        None => &path,
+      };
+      let name = jar::join_entry(prefix, &jar::entry_name(relative));
+      let content = utils::load_content_raw(&path)?.ok_or_else(|| anyhow::Error::msg(format!("open {} failed", path.display())))?;
+      let content = if resource.filter {
+        substitute(&String::from_utf8(content).with_context(|| format!("filter {}", path.display()))?, &vars).into_bytes()
+      } else {
+        content
+      };
+      jar.add(name, content);
+    }
+  }
+  Ok(())
+}
+
+/// replace `${key}` with known variables, unknown ones are kept as-is
+fn substitute(content: &str, vars: &BTreeMap<&str, String>) -> String {
+  let mut result = String::with_capacity(content.len());
+  let mut rest = content;
+  while let Some(start) = rest.find("${") {
+    result += &rest[..start];
+    match rest[start..].find('}').and_then(|end| vars.get(&rest[start+2..start+end]).map(|v| (end, v))) {
+      Some((end, value)) => {
+        result += value;
+        rest = &rest[start+end+1..];
+      },
+      None => {
+        result += "${";
+        rest = &rest[start+2..];
+      },
+    }
+  }
+  result += rest;
+  result
+}
+
+pub fn load_units(config: &PackageConfig) -> Result<BTreeMap<String, Vec<preprocess::Unit>>, anyhow::Error> {
+  serde_json::from_reader(std::fs::File::open(config.target_dir().join("mods.json")).context("open mods.json")?).context("read mods.json")
+}
+
+/// name of the `target/src_files` list of `target`, preprocessed copies are listed without the plugin
+fn units_file(target: &Target, config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let units = load_units(config)?;
+  preprocess::src_files(target, &units, !uses_plugin(config), config).context("gen src_files")
+}
+
+/// source files compiled into `target`, as listed in `target/src_files`
+pub fn target_sources(target: &Target, config: &PackageConfig) -> Result<Vec<std::path::PathBuf>, anyhow::Error> {
+  let units_file = units_file(target, config)?;
+  Ok(utils::load_content(config.target_dir().join("src_files").join(&units_file))?.unwrap_or_default().lines().map(Into::into).collect())
+}
+
+/// jobs every target depends on: resolve, preprocess, the compiler and the compiler plugins,
+/// the module plugin and those of `[build-dependencies]`
+pub fn prepare_jobs<'a>(scheduler: &mut job::Scheduler<'a>, resolve_opts: resolve::Opts, preprocess_opts: preprocess::Opts, config: &'a PackageConfig) -> Vec<job::JobId> {
+  let coursier = resolve_opts.coursier.clone();
+  let toolchain = scheduler.add("toolchain", &[], move || toolchain::prepare(&coursier, config));
+  let resolve = scheduler.add("resolve", &[], move || resolve::main(resolve_opts, config).context("resolve failed"));
+  let preprocess = scheduler.add("preprocess", &[], move || preprocess::main(preprocess_opts, config).context("preprocess failed"));
+  let plugin = scheduler.add("plugin", &[resolve], move || {
+    let mut plugins = Vec::new();
+    if uses_plugin(config) {
+      let plugin = ensure_plugin(&config.target_dir()).context("write plugin failed")?;
+      plugins.push(format!("-Xplugin:{} -P:moduler:name={}", plugin.display(), config.package.name));
+    }
+    for jar in resolve::scope_classpath(resolve::Scope::Build, config)? {
+      plugins.push(format!("-Xplugin:{}", jar.display()));
+    }
+    let plugin_opts = plugins.join(" ");
+    let _ = utils::compare_and_write(config.target_dir().join("plugin_opts"), plugin_opts.as_bytes())?;
+    Ok(())
+  });
+  vec![resolve, preprocess, toolchain, plugin]
+}
+
+pub fn parallel_jobs(jobs: Option<usize>) -> usize {
+  jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+}
+
 pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
-  let targets = get_target(&opts, &config).context("parse target failed")?;
-  resolve::main(opts.resolve, config).context("resolve failed")?;
-  preprocess::main(opts.preprocess, config).context("preprocess failed")?;
-  ensure_plugin().context("write plugin failed")?;
-  let _ = utils::compare_and_write(target_dir().join("plugin_opts"), format!("-Xplugin:target/plugin.jar -P:moduler:name={}", config.package.name).as_bytes())?;
-  let units: BTreeMap<String, Vec<preprocess::Unit>> = serde_json::from_reader(std::fs::File::open("target/mods.json").context("open mods.json")?).context("read mods.json")?;
+  let targets = get_target(&opts, config).context("parse target failed")?;
+  let Opts { target: target_opts, preprocess: preprocess_opts, resolve: resolve_opts, jobs } = opts;
+  let results = targets.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+  let mut scheduler = job::Scheduler::new();
+  let mut compile_deps = prepare_jobs(&mut scheduler, resolve_opts, preprocess_opts, config);
+  // the compile server runs the resolved scala 2 compiler
+  let server = target_opts.server && uses_plugin(config) && config.toolchain.scalac.is_none();
+  if target_opts.server && !server {
+    warn!("compile server does not support edition {} or a local scalac, compiling without it", config.package.edition);
+  }
+  if server {
+    let started = scheduler.add("server", &compile_deps, || server::start(config).map(|_| ()));
+    compile_deps.push(started);
+  }
+  for (target, result) in targets.into_iter().zip(&results) {
+    let package_target = target.clone();
+    let name = target.name.to_string();
+    let compiled = scheduler.add(format!("compile {}", name), &compile_deps, move || {
+      let units_file = units_file(&target, config)?;
+      let classes = compile_dirty(target, &units_file, config, server)?;
+      *result.lock().unwrap() = Some(classes);
+      Ok(())
+    });
+    scheduler.add(format!("package {}", name), &[compiled], move || {
+      let classes = result.lock().unwrap().clone().expect("compiled classes");
+      package(&package_target, &classes, config).map(|_| ())
+    });
+  }
+  scheduler.run(parallel_jobs(jobs))
+}
+
+/// resolve and preprocess, then type check every target without packaging
+pub fn check(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let targets = get_target(&opts, config).context("parse target failed")?;
+  let Opts { preprocess: preprocess_opts, resolve: resolve_opts, jobs, .. } = opts;
+  let mut scheduler = job::Scheduler::new();
+  let check_deps = prepare_jobs(&mut scheduler, resolve_opts, preprocess_opts, config);
   for target in targets {
-    let units_file = preprocess::src_files(&target, &units, false).context("gen src_files")?;
-    let result = compile(target, "@target/deps.classpath", &format!("@target/src_files/{}", units_file))?;
-    package(&result, &config.resources)?;
+    scheduler.add(format!("check {}", target.name.to_string()), &check_deps, move || {
+      let units_file = units_file(&target, config)?;
+      typecheck(target, &args_file(&format!("src_files/{}", units_file), config), config)
+    });
   }
-  Ok(())
+  scheduler.run(parallel_jobs(jobs))
 }
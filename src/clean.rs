@@ -0,0 +1,112 @@
+use std::path::Path;
+use crate::build::{self, Profile, TargetName};
+use crate::{server, utils};
+use crate::config::{PackageConfig, constant::target_root};
+
+#[derive(Clap)]
+pub struct Opts {
+  /// only clean release artifacts
+  #[clap(long)]
+  pub release: bool,
+  /// only clean artifacts of this target
+  #[clap(long)]
+  pub target: Option<String>,
+  /// only clean dependency resolution caches
+  #[clap(long)]
+  pub deps: bool,
+  /// only clean preprocessed sources
+  #[clap(long)]
+  pub preprocessed: bool,
+}
+
+fn remove<P: AsRef<Path>>(path: P) -> Result<(), anyhow::Error> {
+  let path = path.as_ref();
+  let result = if path.is_dir() {
+    std::fs::remove_dir_all(path)
+  } else {
+    std::fs::remove_file(path)
+  };
+  match result {
+    Ok(()) => info!("removed {}", path.display()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+    Err(e) => return Err(e.into()),
+  }
+  Ok(())
+}
+
+fn remove_glob(pattern: &str) -> Result<(), anyhow::Error> {
+  for path in glob::glob(pattern)?.filter_map(|i| i.ok()) {
+    remove(path)?;
+  }
+  Ok(())
+}
+
+/// lock files left by an interrupted `compare_and_write` make later writes fail,
+/// only called while no other carsier holds the target, see `utils::target_lock`
+fn remove_stale_locks(dir: &Path) -> Result<(), anyhow::Error> {
+  if !dir.is_dir() {
+    return Ok(())
+  }
+  for entry in std::fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      remove_stale_locks(&path)?;
+    } else if path.extension().map(|e| e == "lock").unwrap_or(false) {
+      remove(&path)?;
+    }
+  }
+  Ok(())
+}
+
+fn clean_target(name: &TargetName, profile: Profile, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let jar = build::jar_path(name, profile, config);
+  for ext in &["jar", "stamps", "fingerprint", "log"] {
+    remove(jar.with_extension(ext))?;
+  }
+  let classes = build::classes_dir(name, profile, config);
+  for ext in &["fingerprint", "analysis", "deps"] {
+    remove(classes.with_extension(ext))?;
+  }
+  remove(classes)?;
+  // `<name>`, `<name>~target` and `<name>-<feature>`, see `preprocess::src_files`, and their `.incremental` subsets
+  let src_files = config.target_dir().join("src_files");
+  let name = glob::Pattern::escape(&name.to_string());
+  remove(src_files.join(&name))?;
+  for separator in &["~", "-", "."] {
+    remove_glob(&format!("{}/{}{}*", glob::Pattern::escape(&src_files.to_string_lossy()), name, separator))?;
+  }
+  Ok(())
+}
+
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  // held until cleaned, so no carsier starts meanwhile
+  let lock = utils::target_lock()?;
+  match lock.try_lock() {
+    Ok(()) => (),
+    Err(std::fs::TryLockError::WouldBlock) => return Err(anyhow::Error::msg("target is used by another running carsier, stop it before cleaning")),
+    Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+  }
+  if !opts.release && opts.target.is_none() && !opts.deps && !opts.preprocessed {
+    // every edition of a cross built package has its own server and target dir under the root
+    for edition in config.editions() {
+      server::stop(&config.select_edition(&edition)?)?;
+    }
+    return remove(target_root())
+  }
+  let target_dir = config.target_dir();
+  let profile = Profile::from_release(opts.release);
+  match &opts.target {
+    Some(name) => clean_target(&name.parse()?, profile, config)?,
+    None if opts.release => remove(profile.build_dir(config))?,
+    None => (),
+  }
+  if opts.deps {
+    remove_glob(&format!("{}/deps*", glob::Pattern::escape(&target_dir.to_string_lossy())))?;
+  }
+  if opts.preprocessed {
+    remove(target_dir.join("src"))?;
+    remove(target_dir.join("mods.json"))?;
+    remove(target_dir.join("src_files"))?;
+  }
+  remove_stale_locks(&target_dir)
+}
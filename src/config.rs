@@ -1,5 +1,6 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
+pub mod cfg;
 pub mod constant;
 pub mod repo;
 mod version;
@@ -11,35 +12,296 @@ pub use version::VersionRange;
 /// name = "demo"
 /// version = "0.1.0"
 /// authors = ["Clouds Flowing <clouds.flowing@gmail.com>"]
-/// edition = "2.13"
+/// edition = "2.13"   # or a full version like "2.13.12"
+/// editions = ["2.12", "2.13", "3"]
+/// description = "a demo"
+/// license = "MIT"
+/// org = "com.example"
 ///
 /// [dependencies]
 /// breeze = { version = "*", binary = "maven2" }
+/// spark-sql = { version = "3.5.0", org = "org.apache.spark", provided = true }
+///
+/// [dev-dependencies]
+/// munit = { version = "1.0.0", org = "org.scalameta" }
+///
+/// [build-dependencies]
+/// better-monadic-for = { version = "0.3.1", org = "com.olegpy" }
+///
+/// [target.'cfg(os = "linux")'.dependencies]
+/// netty-transport-native-epoll = { version = "4.1.100.Final", org = "io.netty", java = true }
+///
+/// [overrides]
+/// "org.slf4j:slf4j-api" = "2.0.9"
+///
+/// [patch."io.circe"]
+/// circe-core = { path = "../circe-core" }
 /// ```
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageConfig {
   pub package: Package,
   pub dependencies: BTreeMap<String, DependencyLike>,
+  /// only on the classpath of test and example targets
+  #[serde(default, rename = "dev-dependencies")]
+  pub dev_dependencies: BTreeMap<String, DependencyLike>,
+  /// compiler plugins
+  #[serde(default, rename = "build-dependencies")]
+  pub build_dependencies: BTreeMap<String, DependencyLike>,
   #[serde(default)]
   pub features: BTreeMap<String, FeatureLike>,
   #[serde(default)]
   pub resources: Vec<Resource>,
+  #[serde(default)]
+  pub toolchain: Toolchain,
+  /// versions forced in the whole resolution, keyed by `org:name` or `org::name`
+  #[serde(default)]
+  pub overrides: BTreeMap<String, String>,
+  /// local crates used instead of artifacts of an org, `[patch.<org>]`
+  #[serde(default)]
+  pub patch: BTreeMap<String, BTreeMap<String, Patch>>,
+  /// dependencies only used when the feature expression of the key holds
+  #[serde(default)]
+  pub target: BTreeMap<String, TargetDependencies>,
+  /// `scala-<edition>` when an edition of a cross built package is selected
+  #[serde(skip)]
+  pub edition_dir: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// `[target.<feature-expr>]`, see `cfg::FeatureExpr`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetDependencies {
+  #[serde(default)]
+  pub dependencies: BTreeMap<String, DependencyLike>,
+  #[serde(default, rename = "dev-dependencies")]
+  pub dev_dependencies: BTreeMap<String, DependencyLike>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Package {
   pub name: String,
   pub version: Version,
   #[serde(default)]
   pub authors: Vec<String>,
+  /// edition built by default, one of `editions` if set
   pub edition: String,
+  /// editions to cross build, selected by `--edition` or `--all-editions`
+  #[serde(default)]
+  pub editions: Vec<String>,
   #[serde(default = "constant::default_registry")]
   pub registry: String,
-  #[serde(flatten)]
-  pub others: BTreeMap<String, String>,
+  pub description: Option<String>,
+  /// spdx identifier, e.g. `Apache-2.0`
+  pub license: Option<String>,
+  pub homepage: Option<String>,
+  pub repository: Option<String>,
+  /// group id when published, `registry` if not set
+  pub org: Option<String>,
+  #[serde(default)]
+  pub keywords: Vec<String>,
+  /// set to false to prevent `carsier publish`
+  #[serde(default = "constant::default_publish")]
+  pub publish: bool,
+  pub assembly: Option<Assembly>,
+  #[serde(default)]
+  pub jvm: Jvm,
+}
+
+/// settings of the jvm building and running the package, `[package.jvm]`
+/// ```
+/// [package.jvm]
+/// release = 17
+/// java-home = "/usr/lib/jvm/java-17"
+/// options = ["-Xmx2g"]
+/// properties = { "config.file" = "conf/app.conf" }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Jvm {
+  /// java version to compile for, passed as `-release`
+  pub release: Option<u32>,
+  /// jdk to build with, `JAVA_HOME` or `java` on PATH if not set
+  pub java_home: Option<std::path::PathBuf>,
+  /// passed to java by launchers, e.g. `["-Xmx2g"]`
+  #[serde(default)]
+  pub options: Vec<String>,
+  /// system properties set by launchers
+  #[serde(default)]
+  pub properties: BTreeMap<String, String>,
+}
+impl Jvm {
+  /// `options` followed by `-Dkey=value` of `properties`
+  pub fn launcher_options(&self) -> Vec<String> {
+    self.options.iter().cloned().chain(self.properties.iter().map(|(k, v)| format!("-D{}={}", k, v))).collect()
+  }
+}
+
+impl PackageConfig {
+  /// checks serde could not express, called after load
+  pub fn validate(&self) -> Result<(), anyhow::Error> {
+    let package = &self.package;
+    let err = |msg: String| Err(anyhow::Error::msg(format!("invalid [package]: {}", msg)));
+    // sources are rewritten into package `registry.name`
+    if !is_ident(&package.name) {
+      return err(format!("name {:?} should be a valid scala identifier", package.name))
+    }
+    let core = package.version.split(['-', '+']).next().unwrap_or("");
+    let numbers = core.split('.').collect::<Vec<_>>();
+    if numbers.len() != 3 || numbers.iter().any(|n| n.is_empty() || !n.chars().all(|c| c.is_ascii_digit())) {
+      return err(format!("version {:?} should be like 1.2.3", package.version))
+    }
+    if let Some(org) = &package.org {
+      // maven group ids like `org.scala-lang` and `io.get-coursier`
+      let is_segment = |s: &str| s.chars().next().map(|c| c.is_ascii_alphanumeric() || c == '_').unwrap_or(false)
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+      if !org.split('.').all(is_segment) {
+        return err(format!("org {:?} should be like com.example", org))
+      }
+    }
+    for (key, url) in [("homepage", &package.homepage), ("repository", &package.repository)] {
+      if let Some(url) = url {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+          return err(format!("{} {:?} should be a http(s) url", key, url))
+        }
+      }
+    }
+    if !package.editions.is_empty() && !package.editions.contains(&package.edition) {
+      return err(format!("edition {:?} should be one of editions", package.edition))
+    }
+    let targets = self.target.values().flat_map(|t| t.dependencies.iter().chain(&t.dev_dependencies));
+    let mut deps = self.dependencies.iter().chain(&self.dev_dependencies).chain(&self.build_dependencies).chain(targets);
+    if let Some((name, _)) = deps.find(|(_, dep)| dep.as_dep().cross == Some(Cross::Full)) {
+      if let Some(edition) = self.editions().iter().find(|e| e.split('.').count() < 3) {
+        return Err(anyhow::Error::msg(format!("{} with cross = \"full\" needs a full edition like 2.13.12, not {}", name, edition)))
+      }
+    }
+    for key in self.target.keys() {
+      if let Err(e) = key.parse::<cfg::FeatureExpr>() {
+        return Err(anyhow::Error::msg(format!("invalid [target.{:?}]: {}", key, e)))
+      }
+    }
+    Ok(())
+  }
+
+  /// `[target]` sections whose feature expression holds for `features`
+  pub fn target_dependencies(&self, features: &BTreeSet<String>) -> Vec<&TargetDependencies> {
+    self.target.iter()
+      .filter(|(key, _)| key.parse::<cfg::FeatureExpr>().map(|e| e.eval(features)).unwrap_or(false))
+      .map(|(_, deps)| deps).collect()
+  }
+
+  /// the patch replacing dependency `name` of `org`, from `[patch."<org>"]`
+  pub fn patched(&self, org: &str, name: &str) -> Option<&Patch> {
+    self.patch.get(org).and_then(|patches| patches.get(name))
+  }
+
+  /// editions to cross build, only `edition` if `editions` is not set
+  pub fn editions(&self) -> Vec<String> {
+    if self.package.editions.is_empty() { vec![self.package.edition.clone()] } else { self.package.editions.clone() }
+  }
+
+  /// the config building `edition`, a cross built package also switches
+  /// `target_dir` to `target/scala-<edition>` so editions never share outputs
+  pub fn select_edition(&self, edition: &str) -> Result<Self, anyhow::Error> {
+    if !self.editions().iter().any(|e| e == edition) {
+      return Err(anyhow::Error::msg(format!("edition {} not found in [package] editions", edition)))
+    }
+    let mut config = self.clone();
+    config.package.edition = edition.to_string();
+    config.edition_dir = if self.package.editions.is_empty() { None } else { Some(constant::edition_dir(edition)) };
+    Ok(config)
+  }
+
+  /// outputs of the selected edition
+  pub fn target_dir(&self) -> std::path::PathBuf {
+    match &self.edition_dir {
+      Some(dir) => constant::target_root().join(dir),
+      None => constant::target_root(),
+    }
+  }
+}
+
+/// a scala identifier without backquotes, which a package name has to be
+pub fn is_ident(s: &str) -> bool {
+  s.chars().next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+    && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// suffix of scala artifacts built for `edition`, `3` for every scala 3 edition,
+/// `2.13` for a full version like `2.13.12`
+pub fn binary_version(edition: &str) -> String {
+  if edition == "3" || edition.starts_with("3.") { "3".to_string() } else { edition.split('.').take(2).collect::<Vec<_>>().join(".") }
+}
+
+/// compiler used instead of the one resolved for `edition`
+/// ```
+/// [toolchain]
+/// scalac = "/opt/scala/bin/scalac"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Toolchain {
+  pub scalac: Option<std::path::PathBuf>,
+}
+
+/// a crate at `path` built and put on the classpath instead of the published one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patch {
+  pub path: std::path::PathBuf,
+}
+
+/// a fat jar with all dependencies, configured by `[package.assembly]`
+/// ```
+/// [package.assembly]
+/// main-class = "crates.demo.Main"
+/// merge = [{ pattern = "application.conf", strategy = "concat" }]
+/// shade = [{ from = "com.google.common", to = "shaded.guava" }]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Assembly {
+  /// target to assemble, `main` if exists or `lib`
+  pub target: Option<String>,
+  pub main_class: Option<String>,
+  /// checked before default rules, first matched rule wins
+  #[serde(default)]
+  pub merge: Vec<MergeRule>,
+  #[serde(default)]
+  pub shade: Vec<ShadeRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeRule {
+  /// glob of entry path in jar
+  pub pattern: String,
+  pub strategy: MergeStrategy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+  /// keep the first one, target jar comes first
+  First,
+  Last,
+  /// concat contents with newline
+  Concat,
+  Discard,
+  /// merge lines of `META-INF/services` files
+  Service,
+  /// fail if contents differ
+  Error,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// rename package `from` to `to`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadeRule {
+  pub from: String,
+  pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum DependencyLike {
   Version(VersionRange),
@@ -63,6 +325,14 @@ pub struct Dependency {
   #[serde(default)]
   pub java: bool,
   pub org: Option<String>,
+  /// use artifacts of another scala edition, e.g. `cross = "for3Use2_13"`
+  pub cross: Option<Cross>,
+  /// transitive dependencies left out, `org:name` or `org::name`
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  /// only needed to compile, the runtime provides it, so it is not packaged
+  #[serde(default)]
+  pub provided: bool,
   #[serde(flatten)]
   pub others: BTreeMap<String, String>,
 }
@@ -73,12 +343,45 @@ impl From<VersionRange> for Dependency {
       features: Default::default(),
       java: Default::default(),
       org: Default::default(),
+      cross: Default::default(),
+      exclude: Default::default(),
+      provided: Default::default(),
       others: Default::default(),
     }
   }
 }
+impl Dependency {
+  /// maven artifact of `name` for `edition`, scala ones are suffixed by the binary version
+  pub fn artifact(&self, name: &str, edition: &str) -> String {
+    if self.java {
+      return name.to_string()
+    }
+    let suffix = match (self.cross, binary_version(edition).as_str()) {
+      (Some(Cross::For3Use2_13), "3") => "2.13".to_string(),
+      (Some(Cross::For2_13Use3), "2.13") => "3".to_string(),
+      (Some(Cross::Full), _) => edition.to_string(),
+      (_, version) => version.to_string(),
+    };
+    format!("{}_{}", name, suffix)
+  }
+}
+
+/// fallback for dependencies not published for every edition, as sbt's `CrossVersion`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cross {
+  /// the `_2.13` artifact when building scala 3
+  #[serde(rename = "for3Use2_13")]
+  For3Use2_13,
+  /// the `_3` artifact when building scala 2.13
+  #[serde(rename = "for2_13Use3")]
+  For2_13Use3,
+  /// suffixed by the full compiler version like `_2.13.12`, as most compiler plugins are;
+  /// needs `edition` and `editions` to be full versions
+  #[serde(rename = "full")]
+  Full,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum FeatureLike {
   Group(Vec<String>),
@@ -95,7 +398,114 @@ pub struct Feature {
 
 pub type Version = String;
 
+/// files packaged into target jars
+/// ```
+/// [[resources]]
+/// include = "src/main/resources/**/*"
+/// exclude = ["**/*.bak"]
+/// strip-prefix = "src/main/resources"
+/// target = "conf"
+/// filter = true
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Resource {
   pub include: String,
+  #[serde(default)]
+  pub exclude: Vec<String>,
+  /// removed from the matched paths before placing them in the jar
+  pub strip_prefix: Option<String>,
+  /// directory in the jar the matched files are placed under
+  #[serde(alias = "target")]
+  pub prefix: Option<String>,
+  /// only package into these targets, all targets if empty
+  #[serde(default)]
+  pub targets: Vec<String>,
+  /// only package when any of these features is enabled, always if empty
+  #[serde(default)]
+  pub features: Vec<String>,
+  /// substitute `${package.version}`-style variables in the contents
+  #[serde(default)]
+  pub filter: bool,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{binary_version, Cross, Dependency, PackageConfig};
+
+  fn validate(package: &str) -> Result<(), anyhow::Error> {
+    let config: PackageConfig = toml::from_str(&format!("[package]\nedition = \"2.13\"\n{}\n[dependencies]\n", package))?;
+    config.validate()
+  }
+
+  #[test]
+  fn names() {
+    for name in &["app", "my_app", "_app", "App2"] {
+      assert!(validate(&format!("name = {:?}\nversion = \"0.1.0\"", name)).is_ok(), "{} should be accepted", name);
+    }
+    for name in &["my-app", "2app", "", "a.b", "app!"] {
+      assert!(validate(&format!("name = {:?}\nversion = \"0.1.0\"", name)).is_err(), "{} should be rejected", name);
+    }
+  }
+
+  #[test]
+  fn versions() {
+    for version in &["0.1.0", "1.20.300", "1.0.0-RC1", "1.0.0+build.5"] {
+      assert!(validate(&format!("name = \"app\"\nversion = {:?}", version)).is_ok(), "{} should be accepted", version);
+    }
+    for version in &["1.0", "1", "1.0.0.0", "1..0", "v1.0.0", "1.0.x", ""] {
+      assert!(validate(&format!("name = \"app\"\nversion = {:?}", version)).is_err(), "{} should be rejected", version);
+    }
+  }
+
+  #[test]
+  fn orgs() {
+    for org in &["com.example", "org.scala-lang", "io.get-coursier", "com.typesafe.akka-http", "io.7mind", "org_x"] {
+      assert!(validate(&format!("name = \"app\"\nversion = \"0.1.0\"\norg = {:?}", org)).is_ok(), "{} should be accepted", org);
+    }
+    for org in &["", "com..example", ".com", "com.", "-com.example", "com.exa mple", "com/example"] {
+      assert!(validate(&format!("name = \"app\"\nversion = \"0.1.0\"\norg = {:?}", org)).is_err(), "{} should be rejected", org);
+    }
+  }
+
+  #[test]
+  fn keywords_are_not_limited() {
+    assert!(validate("name = \"app\"\nversion = \"0.1.0\"\nkeywords = [\"a\", \"b\", \"c\", \"d\", \"e\", \"f\", \"a-very-long-maven-keyword\"]").is_ok());
+  }
+
+  #[test]
+  fn binary_versions() {
+    assert_eq!(binary_version("2.13"), "2.13");
+    assert_eq!(binary_version("2.13.12"), "2.13");
+    assert_eq!(binary_version("2.12.18"), "2.12");
+    assert_eq!(binary_version("3"), "3");
+    assert_eq!(binary_version("3.3.1"), "3");
+  }
+
+  #[test]
+  fn artifacts() {
+    let dep = |cross, java| {
+      let mut dep = Dependency::from("1.0".parse::<super::VersionRange>().unwrap());
+      dep.cross = cross;
+      dep.java = java;
+      dep
+    };
+    assert_eq!(dep(None, false).artifact("cats-core", "2.13.12"), "cats-core_2.13");
+    assert_eq!(dep(None, true).artifact("guava", "3"), "guava");
+    assert_eq!(dep(Some(Cross::For3Use2_13), false).artifact("a", "3.3.1"), "a_2.13");
+    assert_eq!(dep(Some(Cross::For3Use2_13), false).artifact("a", "2.12"), "a_2.12");
+    assert_eq!(dep(Some(Cross::For2_13Use3), false).artifact("a", "2.13"), "a_3");
+    assert_eq!(dep(Some(Cross::Full), false).artifact("kind-projector", "2.13.12"), "kind-projector_2.13.12");
+  }
+
+  #[test]
+  fn patches_by_org() {
+    let config: PackageConfig = toml::from_str(concat!(
+      "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2.13\"\n",
+      "[dependencies]\ncirce-core = { org = \"io.circe\", version = \"0.14.6\" }\n",
+      "[patch.\"io.circe\"]\ncirce-core = { path = \"../circe-core\" }\n")).unwrap();
+    assert_eq!(config.patched("io.circe", "circe-core").map(|p| p.path.as_path()), Some(std::path::Path::new("../circe-core")));
+    assert!(config.patched("io.circe", "circe-generic").is_none());
+    assert!(config.patched("crates", "circe-core").is_none());
+  }
 }
@@ -0,0 +1,198 @@
+use std::collections::BTreeSet;
+
+/// a condition on enabled features, written as `linux & !edition3`, `(a | b) & c`,
+/// or as `cfg(all(os = "linux", not(feature = "c")))`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureExpr {
+  Feature(String),
+  /// `key = "value"`, `feature = "a"` is the same as `a`
+  KeyValue(String, String),
+  Not(Box<FeatureExpr>),
+  All(Vec<FeatureExpr>),
+  Any(Vec<FeatureExpr>),
+}
+
+impl FeatureExpr {
+  /// `features` holds enabled feature names and `key=value` pairs
+  pub fn eval(&self, features: &BTreeSet<String>) -> bool {
+    match self {
+      FeatureExpr::Feature(name) => features.contains(name),
+      FeatureExpr::KeyValue(key, value) if key == "feature" => features.contains(value),
+      FeatureExpr::KeyValue(key, value) => features.contains(&format!("{}={}", key, value)),
+      FeatureExpr::Not(expr) => !expr.eval(features),
+      FeatureExpr::All(exprs) => exprs.iter().all(|e| e.eval(features)),
+      FeatureExpr::Any(exprs) => exprs.iter().any(|e| e.eval(features)),
+    }
+  }
+}
+
+impl std::str::FromStr for FeatureExpr {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser { input: s, pos: 0 };
+    let expr = parser.or()?;
+    parser.skip_space();
+    if parser.pos != s.len() {
+      return Err(format!("unexpected {:?} at {} in {:?}", &s[parser.pos..], parser.pos, s))
+    }
+    Ok(expr)
+  }
+}
+
+struct Parser<'a> {
+  input: &'a str,
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn rest(&self) -> &'a str {
+    &self.input[self.pos..]
+  }
+
+  fn skip_space(&mut self) {
+    self.pos = self.input.len() - self.rest().trim_start().len();
+  }
+
+  /// consume `c` after spaces if it is next
+  fn eat(&mut self, c: char) -> bool {
+    self.skip_space();
+    if self.rest().starts_with(c) {
+      self.pos += c.len_utf8();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect(&mut self, c: char) -> Result<(), String> {
+    if self.eat(c) { Ok(()) } else { Err(format!("expect {:?} at {} in {:?}", c, self.pos, self.input)) }
+  }
+
+  fn ident(&mut self) -> Result<String, String> {
+    self.skip_space();
+    let len = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')).unwrap_or(self.rest().len());
+    if len == 0 {
+      return Err(format!("expect a feature at {} in {:?}", self.pos, self.input))
+    }
+    let ident = self.rest()[..len].to_string();
+    self.pos += len;
+    Ok(ident)
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect('"')?;
+    let len = self.rest().find('"').ok_or_else(|| format!("unclosed string in {:?}", self.input))?;
+    let value = self.rest()[..len].to_string();
+    self.pos += len + 1;
+    Ok(value)
+  }
+
+  fn or(&mut self) -> Result<FeatureExpr, String> {
+    let mut exprs = vec![self.and()?];
+    while self.eat('|') {
+      exprs.push(self.and()?);
+    }
+    Ok(if exprs.len() == 1 { exprs.remove(0) } else { FeatureExpr::Any(exprs) })
+  }
+
+  fn and(&mut self) -> Result<FeatureExpr, String> {
+    let mut exprs = vec![self.unary()?];
+    while self.eat('&') {
+      exprs.push(self.unary()?);
+    }
+    Ok(if exprs.len() == 1 { exprs.remove(0) } else { FeatureExpr::All(exprs) })
+  }
+
+  /// `a, b, c)` after the opening parenthesis
+  fn list(&mut self) -> Result<Vec<FeatureExpr>, String> {
+    let mut exprs = Vec::new();
+    while !self.eat(')') {
+      exprs.push(self.or()?);
+      if !self.eat(',') {
+        self.expect(')')?;
+        break
+      }
+    }
+    Ok(exprs)
+  }
+
+  fn unary(&mut self) -> Result<FeatureExpr, String> {
+    if self.eat('!') {
+      return Ok(FeatureExpr::Not(Box::new(self.unary()?)))
+    }
+    if self.eat('(') {
+      let expr = self.or()?;
+      self.expect(')')?;
+      return Ok(expr)
+    }
+    let ident = self.ident()?;
+    if self.eat('(') {
+      let mut exprs = self.list()?;
+      return match (ident.as_str(), exprs.len()) {
+        ("cfg", 1) => Ok(exprs.remove(0)),
+        ("not", 1) => Ok(FeatureExpr::Not(Box::new(exprs.remove(0)))),
+        ("all", _) => Ok(FeatureExpr::All(exprs)),
+        ("any", _) => Ok(FeatureExpr::Any(exprs)),
+        _ => Err(format!("unknown {}(...) with {} arguments in {:?}", ident, exprs.len(), self.input)),
+      }
+    }
+    if self.eat('=') {
+      return Ok(FeatureExpr::KeyValue(ident, self.string()?))
+    }
+    Ok(FeatureExpr::Feature(ident))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::FeatureExpr;
+  use std::collections::BTreeSet;
+
+  fn features(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|s| s.to_string()).collect()
+  }
+
+  fn eval(expr: &str, names: &[&str]) -> bool {
+    expr.parse::<FeatureExpr>().unwrap().eval(&features(names))
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or() {
+    let expr: FeatureExpr = "a | b & c".parse().unwrap();
+    let feature = |s: &str| FeatureExpr::Feature(s.to_string());
+    assert_eq!(expr, FeatureExpr::Any(vec![feature("a"), FeatureExpr::All(vec![feature("b"), feature("c")])]));
+    assert!(eval("a | b & c", &["a"]));
+    assert!(!eval("a | b & c", &["b"]));
+    assert!(eval("(a | b) & c", &["b", "c"]));
+  }
+
+  #[test]
+  fn not() {
+    assert!(eval("!a", &[]));
+    assert!(!eval("!a & b", &["a", "b"]));
+    assert!(eval("!!a", &["a"]));
+    assert!(eval("linux & !edition3", &["linux", "edition2_13"]));
+  }
+
+  #[test]
+  fn cfg_form() {
+    assert!(eval(r#"cfg(all(os = "linux", not(feature = "c")))"#, &["os=linux"]));
+    assert!(!eval(r#"cfg(all(os = "linux", not(feature = "c")))"#, &["os=linux", "c"]));
+    assert!(eval(r#"cfg(any(unix, windows))"#, &["unix"]));
+    assert!(!eval("any()", &["a"]));
+    assert!(eval("all()", &[]));
+  }
+
+  #[test]
+  fn trailing_comma() {
+    assert!(eval("all(a, b,)", &["a", "b"]));
+    assert!(eval(r#"cfg(any(os = "macos", a,),)"#, &["a"]));
+  }
+
+  #[test]
+  fn errors() {
+    for expr in &["", "a |", "a & (b", "!", "all(a b)", "cfg(a, b)", "not()", "foo(a)", r#"os = "linux"#, "os = linux", "a b"] {
+      assert!(expr.parse::<FeatureExpr>().is_err(), "{:?} should not parse", expr);
+    }
+  }
+}
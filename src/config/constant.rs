@@ -23,21 +23,44 @@ pub fn toml_name() -> String {
   format!("{}.toml", first_letter_to_uppper_case(NAME))
 }
 
-pub fn target_dir() -> std::path::PathBuf {
+/// directory under `target` holding outputs of `edition`
+pub fn edition_dir(edition: &str) -> String {
+  format!("scala-{}", edition)
+}
+
+/// holds logs and outputs of every edition
+pub fn target_root() -> std::path::PathBuf {
   std::path::Path::new("target").to_owned()
 }
 
+/// `$CARSIER_HOME` or `~/.carsier`
+pub fn home_dir() -> std::path::PathBuf {
+  if let Some(home) = std::env::var_os("CARSIER_HOME") {
+    return home.into()
+  }
+  let user_home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).unwrap_or_else(|| ".".into());
+  std::path::Path::new(&user_home).join(format!(".{}", NAME))
+}
+
+pub fn repo_config() -> std::path::PathBuf {
+  home_dir().join("repo.toml")
+}
+
 pub fn default_registry() -> String {
   REGISTRY.to_string()
 }
 
-pub fn ensure_plugin() -> Result<std::path::PathBuf, anyhow::Error> {
+pub fn default_publish() -> bool {
+  true
+}
+
+pub fn ensure_plugin(target_dir: &std::path::Path) -> Result<std::path::PathBuf, anyhow::Error> {
   use std::io::Write;
-  let plugin_path = std::path::Path::new("target/plugin.jar").to_owned();
+  let plugin_path = target_dir.join("plugin.jar");
   if plugin_path.exists() {
     return Ok(plugin_path)
   }
-  std::fs::create_dir_all("target")?;
+  std::fs::create_dir_all(target_dir)?;
   let mut f = std::fs::File::create(&plugin_path)?;
   f.write(include_bytes!("../../configs/plugin.jar"))?;
   Ok(plugin_path)
@@ -17,3 +17,12 @@ pub enum RepoLike {
 pub struct Repo {
   url: String,
 }
+
+impl RepoConfig {
+  pub fn url(&self, name: &str) -> Option<&str> {
+    self.repos.get(name).map(|repo| match repo {
+      RepoLike::Url(url) => url.as_str(),
+      RepoLike::Full(repo) => repo.url.as_str(),
+    })
+  }
+}
@@ -0,0 +1,32 @@
+use anyhow::Context;
+use crate::{build, resolve, toolchain};
+use crate::build::{TargetName, Profile};
+use crate::config::PackageConfig;
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  /// do not import `registry.crate._` on start
+  #[clap(long)]
+  pub no_import: bool,
+}
+
+/// build the lib target and start the repl of the toolchain with it and the dev dependencies on classpath
+pub fn main(mut opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  opts.build.target.targets = vec![TargetName::Lib.to_string()];
+  let profile = Profile::from_release(opts.build.target.release);
+  build::main(opts.build, config).context("build failed")?;
+  let toolchain = toolchain::load(config)?;
+  let classpath = resolve::scope_classpath(resolve::Scope::Dev, config)?.into_iter().chain(toolchain.library()).chain(Some(build::jar_path(&TargetName::Lib, profile, config)));
+  let mut command = toolchain.tool("scala", toolchain::repl_main(&config.package.edition), &config.package.jvm.launcher_options())?;
+  command.args(&toolchain.options).arg("-classpath").arg(std::env::join_paths(classpath)?);
+  // the scala 3 repl has no init script
+  if !opts.no_import && crate::config::binary_version(&config.package.edition) != "3" {
+    let init = config.target_dir().join("console.scala");
+    let _ = utils::compare_and_write(&init, format!("import {}.{}._\n", config.package.registry, config.package.name).as_bytes())?;
+    command.arg("-i").arg(init);
+  }
+  utils::exec(&mut command)
+}
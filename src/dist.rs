@@ -0,0 +1,119 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use crate::{build, resolve, toolchain};
+use crate::build::{TargetName, Profile};
+use crate::config::PackageConfig;
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+}
+
+/// quote for posix shell
+fn quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// a posix shell launcher running `main_class` with all jars in `lib_dir`,
+/// `lib_dir` is put in double quotes so it could refer to shell variables
+pub fn launcher(lib_dir: &str, main_class: &str, options: &[String]) -> String {
+  let options = options.iter().map(|o| format!(" {}", quote(o))).collect::<String>();
+  format!(concat!(
+    "#!/bin/sh\n",
+    "APP_HOME=\"$(cd \"$(dirname \"$0\")/..\" && pwd)\"\n",
+    "exec \"${{JAVA:-${{JAVA_HOME:+$JAVA_HOME/bin/}}java}}\" $JAVA_OPTS{} -cp \"{}/*\" {} \"$@\"\n"),
+    options, lib_dir, main_class)
+}
+
+pub fn set_executable(path: &Path) -> Result<(), anyhow::Error> {
+  #[cfg(unix)] {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+  }
+  #[cfg(not(unix))]
+  let _ = path;
+  Ok(())
+}
+
+/// copy the bin target jar, its runtime classpath and the scala library into `lib_dir`
+pub fn copy_jars(lib_dir: &Path, config: &PackageConfig, profile: Profile) -> Result<(), anyhow::Error> {
+  let own_jar = build::jar_path(&TargetName::BinMain, profile, config);
+  let library = toolchain::load(config)?.library();
+  if library.is_empty() {
+    warn!("scala library of scalac in [toolchain] is unknown, add it to [dependencies] if the launcher needs it");
+  }
+  let jars = resolve::scope_classpath(resolve::Scope::Runtime, config)?.into_iter().chain(library).collect::<Vec<_>>();
+  copy_into(lib_dir, &own_jar, &format!("{}-{}.jar", config.package.name, config.package.version), &jars)
+}
+
+/// `own_jar` as `own_name` then `jars`, a jar is skipped if one of the same file name is copied already
+fn copy_into(lib_dir: &Path, own_jar: &Path, own_name: &str, jars: &[PathBuf]) -> Result<(), anyhow::Error> {
+  std::fs::create_dir_all(lib_dir)?;
+  std::fs::copy(own_jar, lib_dir.join(own_name)).with_context(|| format!("copy {}", own_jar.display()))?;
+  let mut copied = BTreeSet::new();
+  copied.insert(std::ffi::OsString::from(own_name));
+  for jar in jars {
+    let filename = jar.file_name().ok_or_else(|| anyhow::Error::msg(format!("invalid classpath {}", jar.display())))?;
+    if copied.insert(filename.to_owned()) {
+      std::fs::copy(jar, lib_dir.join(filename)).with_context(|| format!("copy {}", jar.display()))?;
+    }
+  }
+  Ok(())
+}
+
+fn archive(dist_dir: &Path, dirname: &str) -> Result<std::path::PathBuf, anyhow::Error> {
+  let archive_path = dist_dir.with_file_name(format!("{}.tar.gz", dirname));
+  let file = std::fs::File::create(&archive_path)?;
+  let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+  builder.append_dir_all(dirname, dist_dir)?;
+  builder.into_inner()?.finish()?;
+  Ok(archive_path)
+}
+
+/// build the bin target into `target/dist/<name>-<version>` with `lib`, `bin` and a `.tar.gz`
+pub fn main(mut opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  opts.build.target.targets = vec![TargetName::BinMain.to_string()];
+  let profile = Profile::from_release(opts.build.target.release);
+  build::main(opts.build, config).context("build failed")?;
+  let main_class = build::main_class(&TargetName::BinMain, config).expect("bin target has main class");
+  let dirname = format!("{}-{}", config.package.name, config.package.version);
+  let dist_dir = config.target_dir().join("dist").join(&dirname);
+  let _ = std::fs::remove_dir_all(&dist_dir);
+  copy_jars(&dist_dir.join("lib"), config, profile)?;
+  let bin = dist_dir.join("bin").join(&config.package.name);
+  let _ = utils::compare_and_write(&bin, launcher("$APP_HOME/lib", &main_class, &config.package.jvm.launcher_options()).as_bytes())?;
+  set_executable(&bin)?;
+  let archive_path = archive(&dist_dir, &dirname)?;
+  info!("packaged: {} => {}", dist_dir.display(), archive_path.display());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::copy_into;
+  use crate::utils;
+
+  #[test]
+  fn library_jars_are_copied_once() {
+    let dir = utils::test_dir("dist-copy-into");
+    let jar = |path: &str| {
+      let path = dir.join(path);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(&path, path.display().to_string()).unwrap();
+      path
+    };
+    let own = jar("build/main.jar");
+    let deps = vec![jar("cache/cats-core_2.13-2.10.0.jar"), jar("cache/scala-library-2.13.12.jar"), jar("toolchain/scala-library-2.13.12.jar")];
+    let lib = dir.join("dist/lib");
+    copy_into(&lib, &own, "app-0.1.0.jar", &deps).unwrap();
+    let mut names = std::fs::read_dir(&lib).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["app-0.1.0.jar", "cats-core_2.13-2.10.0.jar", "scala-library-2.13.12.jar"]);
+    // the first one of a name wins
+    assert_eq!(std::fs::read_to_string(lib.join("scala-library-2.13.12.jar")).unwrap(), deps[1].display().to_string());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,57 @@
+use anyhow::Context;
+use crate::{build, resolve, preprocess, toolchain};
+use crate::build::{Target, TargetName, Profile};
+use crate::config::PackageConfig;
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub preprocess: preprocess::Opts,
+  #[clap(flatten)]
+  pub resolve: resolve::Opts,
+  /// print path of the generated index.html
+  #[clap(long)]
+  pub open: bool,
+  /// include private members
+  #[clap(long)]
+  pub document_private_items: bool,
+}
+
+/// run scaladoc of the toolchain over the preprocessed sources of the lib target,
+/// the output goes to `target/doc/<crate>`
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let features = opts.resolve.features.clone();
+  toolchain::prepare(&opts.resolve.coursier, config).context("prepare toolchain failed")?;
+  let toolchain = toolchain::load(config)?;
+  if toolchain.compiler_jars().is_some() && crate::config::binary_version(&config.package.edition) == "3" {
+    return Err(anyhow::Error::msg("doc of scala 3 editions is not supported, its scaladoc is not part of the compiler"))
+  }
+  resolve::main(opts.resolve, config).context("resolve failed")?;
+  preprocess::main(opts.preprocess, config).context("preprocess failed")?;
+  let units = build::load_units(config)?;
+  let target = Target { name: TargetName::Lib, profile: Profile::Debug, features: build::target_features(&features, config)? };
+  let units_file = preprocess::src_files(&target, &units, true, config).context("lib target not found")?;
+  let out_dir = config.target_dir().join("doc").join(&config.package.name);
+  std::fs::create_dir_all(&out_dir)?;
+  let classpath = std::env::join_paths(resolve::classpath(config)?.into_iter().chain(toolchain.library()))?;
+  let mut args = vec![
+    "-classpath".to_string(), classpath.to_string_lossy().to_string(),
+    "-d".to_string(), out_dir.display().to_string(),
+    "-doc-title".to_string(), config.package.name.clone(),
+    "-doc-version".to_string(), config.package.version.clone(),
+  ];
+  if opts.document_private_items {
+    args.push("-private".to_string());
+  }
+  args.push(build::args_file(&format!("src_files/{}", units_file), config));
+  utils::call_command(toolchain.tool("scaladoc", "scala.tools.nsc.ScalaDoc", &[])?.args(&toolchain.options).args(&args))?;
+  // sources are rewritten into `registry.crate`, so the crate's own package is the entry
+  let index = out_dir.join(&config.package.registry).join(&config.package.name).join("index.html");
+  let index = if index.exists() { index } else { out_dir.join("index.html") };
+  info!("documented: {} => {}", config.package.name, out_dir.display());
+  if opts.open {
+    println!("{}", index.display());
+  }
+  Ok(())
+}
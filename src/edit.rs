@@ -0,0 +1,146 @@
+use std::path::Path;
+use anyhow::Context;
+use crate::config::PackageConfig;
+use crate::utils;
+
+#[derive(Clap)]
+pub struct AddOpts {
+  /// `org::name[@version]` for scala, `org:name[@version]` for java, or `name[@version]` for crates
+  pub spec: String,
+  /// treat as a java dependency even with `::`
+  #[clap(long)]
+  pub java: bool,
+  #[clap(long, default_value = "coursier")]
+  pub coursier: String,
+}
+
+#[derive(Clap)]
+pub struct RemoveOpts {
+  pub name: String,
+}
+
+/// a dependency written as in the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+  pub org: Option<String>,
+  pub name: String,
+  pub java: bool,
+  pub version: Option<String>,
+}
+impl std::str::FromStr for Spec {
+  type Err = anyhow::Error;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (s, version) = match s.rfind('@') {
+      Some(i) => (&s[..i], Some(s[i+1..].to_string())),
+      None => (s, None),
+    };
+    let (org, name, java) = if let Some(i) = s.find("::") {
+      (Some(&s[..i]), &s[i+2..], false)
+    } else if let Some(i) = s.find(':') {
+      (Some(&s[..i]), &s[i+1..], true)
+    } else {
+      (None, s, false)
+    };
+    if name.is_empty() || name.contains(':') || org.map(|o| o.is_empty()).unwrap_or(false) {
+      return Err(anyhow::Error::msg(format!("invalid dependency {}", s)))
+    }
+    Ok(Self { org: org.map(|o| o.to_string()), name: name.to_string(), java, version })
+  }
+}
+impl Spec {
+  /// maven artifact name, scala ones are suffixed by the binary version of edition
+  pub fn artifact(&self, edition: &str) -> String {
+    if self.java { self.name.clone() } else { format!("{}_{}", self.name, crate::config::binary_version(edition)) }
+  }
+}
+
+/// `1.0-RC1`, `2.0.0-M3`, `3.1-beta.2` or `1.0-SNAPSHOT`, while `32.1.3-jre` is a release
+fn is_pre_release(version: &str) -> bool {
+  version.split(['-', '.', '+']).skip(1).any(|part| {
+    let qualifier = part.trim_end_matches(|c: char| c.is_ascii_digit()).to_ascii_lowercase();
+    ["rc", "m", "alpha", "a", "beta", "b", "snapshot", "milestone", "cr", "pre", "preview", "dev", "ea"].contains(&qualifier.as_str())
+  })
+}
+
+/// the last release `coursier complete` lists, versions come in ascending order
+fn latest_version(coursier: &str, org: &str, artifact: &str) -> Result<String, anyhow::Error> {
+  let output = utils::call(coursier, ["complete", &format!("{}:{}:", org, artifact)])?;
+  output.lines().map(|l| l.trim()).rfind(|l| !l.is_empty() && !is_pre_release(l)).map(|l| l.to_string())
+    .ok_or_else(|| anyhow::Error::msg(format!("no release found for {}:{}", org, artifact)))
+}
+
+fn load_document(path: &Path) -> Result<toml_edit::DocumentMut, anyhow::Error> {
+  let content = utils::load_content(path)?.ok_or_else(|| anyhow::Error::msg("open config file"))?;
+  content.parse().with_context(|| format!("parse {}", path.display()))
+}
+
+pub fn add(opts: AddOpts, config_path: &Path, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let mut spec: Spec = opts.spec.parse()?;
+  spec.java |= opts.java;
+  let version = match (&spec.version, &spec.org) {
+    (Some(version), _) => version.clone(),
+    (None, Some(org)) => latest_version(&opts.coursier, org, &spec.artifact(&config.package.edition))?,
+    (None, None) => "*".to_string(),
+  };
+  let mut doc = load_document(config_path)?;
+  let deps = doc["dependencies"].or_insert(toml_edit::table()).as_table_mut()
+    .ok_or_else(|| anyhow::Error::msg("dependencies is not a table"))?;
+  let value = match &spec.org {
+    None => toml_edit::value(version.as_str()),
+    Some(org) => {
+      let mut dep = toml_edit::InlineTable::new();
+      dep.insert("version", version.as_str().into());
+      dep.insert("org", org.as_str().into());
+      if spec.java {
+        dep.insert("java", true.into());
+      }
+      toml_edit::value(dep)
+    },
+  };
+  let replaced = deps.insert(&spec.name, value).is_some();
+  let _ = utils::compare_and_write(config_path, doc.to_string().as_bytes())?;
+  info!("{} {} = {}", if replaced { "updated" } else { "added" }, spec.name, version);
+  Ok(())
+}
+
+pub fn remove(opts: RemoveOpts, config_path: &Path) -> Result<(), anyhow::Error> {
+  let mut doc = load_document(config_path)?;
+  let removed = doc.get_mut("dependencies").and_then(|deps| deps.as_table_like_mut()).and_then(|deps| deps.remove(&opts.name));
+  if removed.is_none() {
+    return Err(anyhow::Error::msg(format!("dependency {} not found", opts.name)))
+  }
+  let _ = utils::compare_and_write(config_path, doc.to_string().as_bytes())?;
+  info!("removed {}", opts.name);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{is_pre_release, Spec};
+
+  fn spec(org: Option<&str>, name: &str, java: bool, version: Option<&str>) -> Spec {
+    Spec { org: org.map(String::from), name: name.to_string(), java, version: version.map(String::from) }
+  }
+
+  #[test]
+  fn specs() {
+    assert_eq!("com.google.guava:guava".parse::<Spec>().unwrap(), spec(Some("com.google.guava"), "guava", true, None));
+    assert_eq!("org.typelevel::cats-core@2.10.0".parse::<Spec>().unwrap(), spec(Some("org.typelevel"), "cats-core", false, Some("2.10.0")));
+    assert_eq!("io.circe::circe-core".parse::<Spec>().unwrap(), spec(Some("io.circe"), "circe-core", false, None));
+    assert_eq!("com.google.guava:guava@32.1.3-jre".parse::<Spec>().unwrap(), spec(Some("com.google.guava"), "guava", true, Some("32.1.3-jre")));
+    assert_eq!("my_crate@0.1".parse::<Spec>().unwrap(), spec(None, "my_crate", false, Some("0.1")));
+    for malformed in &["", "@1.0", "org:", "org::", ":name", "::name", "a:b:c", "a::b::c"] {
+      assert!(malformed.parse::<Spec>().is_err(), "{} should be rejected", malformed);
+    }
+  }
+
+  #[test]
+  fn pre_releases() {
+    for version in &["1.0-RC1", "2.0.0-M3", "3.1-beta.2", "1.0-SNAPSHOT", "1.0.0-alpha", "2.13.0-RC2", "1.0.0-rc.1"] {
+      assert!(is_pre_release(version), "{}", version);
+    }
+    for version in &["1.0", "2.10.0", "32.1.3-jre", "1.2.3+4", "3.3.1", "1.0.0-final"] {
+      assert!(!is_pre_release(version), "{}", version);
+    }
+  }
+}
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use crate::{build, job, toolchain};
+use crate::build::{Profile, Target};
+use crate::config::{PackageConfig, constant::*};
+use crate::utils;
+
+const BLOOP_VERSION: &str = "1.4.0";
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+}
+
+#[derive(Debug, Serialize)]
+struct BloopFile {
+  version: &'static str,
+  project: BloopProject,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BloopProject {
+  name: String,
+  directory: PathBuf,
+  workspace_dir: PathBuf,
+  sources: Vec<PathBuf>,
+  dependencies: Vec<String>,
+  classpath: Vec<PathBuf>,
+  out: PathBuf,
+  classes_dir: PathBuf,
+  scala: BloopScala,
+  java: BloopJava,
+  tags: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct BloopScala {
+  organization: &'static str,
+  name: &'static str,
+  version: String,
+  options: Vec<String>,
+  jars: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct BloopJava {
+  options: Vec<String>,
+}
+
+fn bloop_dir() -> PathBuf {
+  Path::new(".bloop").to_owned()
+}
+
+fn stamp_file() -> PathBuf {
+  bloop_dir().join(format!("{}.stamp", NAME))
+}
+
+/// changes with the config, the resolved classpath or the module layout
+fn stamp(config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let mut content = Vec::new();
+  for path in &[PathBuf::from(toml_name()), config.target_dir().join("deps.classpath"), config.target_dir().join("deps-dev.classpath"), config.target_dir().join("mods.json")] {
+    content.extend(utils::load_content_raw(path)?.unwrap_or_default());
+    content.push(0);
+  }
+  Ok(format!("{:016x}", utils::hash_content(&content)))
+}
+
+/// toolchain and plugin options with the plugin jar made absolute, bloop compiles from its own working dir
+pub fn scalac_options(workspace: &Path, config: &PackageConfig) -> Result<Vec<String>, anyhow::Error> {
+  let mut options = toolchain::release_options(config);
+  options.push("-sourcepath".to_string());
+  options.push(workspace.join(build::source_path(config)).display().to_string());
+  for opt in utils::read_args_file(config.target_dir().join("plugin_opts"))? {
+    options.push(match opt.strip_prefix("-Xplugin:") {
+      Some(path) => format!("-Xplugin:{}", workspace.join(path).display()),
+      None => opt,
+    });
+  }
+  Ok(options)
+}
+
+/// dependencies of the target and the scala library of the compiler `jars`, editors don't add it themselves
+pub fn classpath(target: &Target, workspace: &Path, jars: &[PathBuf], config: &PackageConfig) -> Result<Vec<PathBuf>, anyhow::Error> {
+  Ok(crate::resolve::scope_classpath(build::dependency_scope(target), config)?.into_iter().map(|p| workspace.join(p))
+    .chain(toolchain::library_jars(jars)).collect())
+}
+
+fn project(target: &Target, workspace: &Path, scala: (&str, &[PathBuf]), config: &PackageConfig) -> Result<BloopProject, anyhow::Error> {
+  let name = format!("{}-{}", config.package.name, target.name.to_string());
+  let sources = build::target_sources(target, config)?.into_iter().map(|f| workspace.join(f)).collect();
+  let out = workspace.join(bloop_dir()).join(&name);
+  Ok(BloopProject {
+    directory: workspace.to_owned(),
+    workspace_dir: workspace.to_owned(),
+    sources,
+    dependencies: Vec::new(),
+    classpath: classpath(target, workspace, scala.1, config)?,
+    classes_dir: out.join("classes"),
+    out,
+    scala: BloopScala {
+      organization: "org.scala-lang",
+      name: toolchain::compiler_artifact(&config.package.edition),
+      version: scala.0.to_string(),
+      options: scalac_options(workspace, config)?,
+      jars: scala.1.to_vec(),
+    },
+    java: BloopJava { options: Vec::new() },
+    tags: vec!["library"],
+    name,
+  })
+}
+
+/// write one bloop file per target, files of targets no longer found are removed
+fn write(targets: &[Target], coursier: &str, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let workspace = std::env::current_dir()?;
+  let (version, jars) = toolchain::resolve(coursier, config).context("resolve scala compiler")?;
+  std::fs::create_dir_all(bloop_dir())?;
+  let mut written = Vec::new();
+  for target in targets {
+    let project = project(target, &workspace, (&version, &jars), config)?;
+    let path = bloop_dir().join(format!("{}.json", project.name));
+    let content = serde_json::to_string_pretty(&BloopFile { version: BLOOP_VERSION, project })?;
+    let _ = utils::compare_and_write(&path, content.as_bytes())?;
+    written.push(path);
+  }
+  for entry in std::fs::read_dir(bloop_dir())? {
+    let path = entry?.path();
+    if path.extension().map(|e| e == "json").unwrap_or(false) && !written.contains(&path) {
+      let _ = std::fs::remove_file(&path);
+    }
+  }
+  let _ = utils::compare_and_write(stamp_file(), stamp(config)?.as_bytes())?;
+  info!("bloop config: {} targets => {}", written.len(), bloop_dir().display());
+  Ok(())
+}
+
+/// regenerate bloop files after a build if `carsier ide` was run before and the project changed
+pub fn refresh(coursier: &str, features: &[String], config: &PackageConfig) -> Result<(), anyhow::Error> {
+  if !bloop_dir().exists() || utils::load_content(stamp_file())?.as_deref() == Some(stamp(config)?.as_str()) {
+    return Ok(())
+  }
+  write(&build::find_targets(&[], Profile::Debug, features, config)?, coursier, config)
+}
+
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let targets = build::get_target(&opts.build, config).context("parse target failed")?;
+  let build::Opts { preprocess, resolve, jobs, .. } = opts.build;
+  let coursier = resolve.coursier.clone();
+  let mut scheduler = job::Scheduler::new();
+  build::prepare_jobs(&mut scheduler, resolve, preprocess, config);
+  scheduler.run(build::parallel_jobs(jobs))?;
+  write(&targets, &coursier, config)
+}
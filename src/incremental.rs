@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use crate::utils;
+
+/// what the compile server recorded of a source, classes are internal names like `a/b/Foo`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Source {
+  pub hash: String,
+  /// top level classes, traits and objects of the source
+  pub defines: BTreeSet<String>,
+  /// top level classes of other sources and the classpath it refers to
+  pub uses: BTreeSet<String>,
+}
+
+/// `<classes>.analysis`, the sources a classes dir was compiled from,
+/// written only after a successful compile in the compile server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Analysis {
+  /// fingerprint of the classpath, plugin options and compiler, any change recompiles everything
+  pub environment: String,
+  /// by canonical path
+  pub sources: BTreeMap<PathBuf, Source>,
+}
+
+/// sources to recompile, and removed sources whose classes are deleted
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Invalidation {
+  pub compile: BTreeSet<PathBuf>,
+  pub removed: BTreeSet<PathBuf>,
+}
+
+impl Invalidation {
+  pub fn is_empty(&self) -> bool {
+    self.compile.is_empty() && self.removed.is_empty()
+  }
+}
+
+impl Analysis {
+  pub fn new(environment: String) -> Self {
+    Analysis { environment, sources: BTreeMap::new() }
+  }
+
+  /// `None` if there is no analysis or it is unreadable, which recompiles everything
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, anyhow::Error> {
+    Ok(utils::load_content(path)?.and_then(|content| serde_json::from_str(&content).ok()))
+  }
+
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+    let _ = utils::compare_and_write(path, serde_json::to_string_pretty(self)?.as_bytes())?;
+    Ok(())
+  }
+
+  /// new and changed sources, then every source using a class defined by an invalidated
+  /// or removed source, until no more sources are invalidated
+  pub fn invalidate(&self, hashes: &BTreeMap<PathBuf, String>) -> Invalidation {
+    let removed = self.sources.keys().filter(|p| !hashes.contains_key(*p)).cloned().collect::<BTreeSet<_>>();
+    let mut compile = hashes.iter()
+      .filter(|(path, hash)| self.sources.get(*path).map(|s| &s.hash != *hash).unwrap_or(true))
+      .map(|(path, _)| path.clone()).collect::<BTreeSet<_>>();
+    let mut invalid = removed.iter().chain(&compile).filter_map(|p| self.sources.get(p))
+      .flat_map(|s| s.defines.iter().cloned()).collect::<BTreeSet<_>>();
+    loop {
+      let dependents = self.sources.iter()
+        .filter(|(path, source)| hashes.contains_key(*path) && !compile.contains(*path) && !source.uses.is_disjoint(&invalid))
+        .map(|(path, _)| path.clone()).collect::<Vec<_>>();
+      if dependents.is_empty() {
+        return Invalidation { compile, removed }
+      }
+      for path in dependents {
+        invalid.extend(self.sources[&path].defines.iter().cloned());
+        compile.insert(path);
+      }
+    }
+  }
+
+  /// delete the class files of the classes `sources` defined: `Foo.class`, `Foo$.class` and nested `Foo$*.class`
+  pub fn remove_classes<'a, I: IntoIterator<Item = &'a PathBuf>>(&self, classes: &Path, sources: I) -> Result<(), anyhow::Error> {
+    for name in sources.into_iter().filter_map(|p| self.sources.get(p)).flat_map(|s| &s.defines) {
+      let class = classes.join(name);
+      let (dir, base) = match (class.parent(), class.file_name()) {
+        (Some(dir), Some(base)) if dir.is_dir() => (dir, base.to_string_lossy().to_string()),
+        _ => continue,
+      };
+      for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if file_name == format!("{}.class", base) || (file_name.starts_with(&format!("{}$", base)) && file_name.ends_with(".class")) {
+          std::fs::remove_file(&path)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// the analysis after `compiled` were recompiled and the sources not in `hashes` removed,
+  /// `None` if the compile server did not report every compiled source
+  pub fn update(mut self, hashes: &BTreeMap<PathBuf, String>, compiled: &BTreeSet<PathBuf>, report: &str) -> Option<Self> {
+    let mut reported = parse(report);
+    self.sources.retain(|path, _| hashes.contains_key(path));
+    for path in compiled {
+      let mut source = reported.remove(path)?;
+      source.hash = hashes.get(path)?.clone();
+      self.sources.insert(path.clone(), source);
+    }
+    Some(self)
+  }
+}
+
+/// the analysis file of the compile server, `source <path>` followed by its `define <class>` and `use <class>` lines
+pub fn parse(report: &str) -> BTreeMap<PathBuf, Source> {
+  let mut result = BTreeMap::<PathBuf, Source>::new();
+  let mut current = None;
+  for line in report.lines() {
+    match line.split_once(' ') {
+      Some(("source", path)) => {
+        let path = PathBuf::from(path);
+        result.entry(path.clone()).or_default();
+        current = Some(path);
+      },
+      Some(("define", class)) => if let Some(source) = current.as_ref().and_then(|p| result.get_mut(p)) {
+        source.defines.insert(class.to_string());
+      },
+      Some(("use", class)) => if let Some(source) = current.as_ref().and_then(|p| result.get_mut(p)) {
+        source.uses.insert(class.to_string());
+      },
+      _ => {},
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn source(hash: &str, defines: &[&str], uses: &[&str]) -> Source {
+    Source {
+      hash: hash.to_string(),
+      defines: defines.iter().map(|s| s.to_string()).collect(),
+      uses: uses.iter().map(|s| s.to_string()).collect(),
+    }
+  }
+
+  /// `a` defines `A`, `b` uses `A`, `c` uses `B` of `b`, `d` stands alone
+  fn analysis() -> Analysis {
+    let mut analysis = Analysis::new("env".to_string());
+    analysis.sources.insert("a.scala".into(), source("1", &["p/A"], &["scala/Int"]));
+    analysis.sources.insert("b.scala".into(), source("2", &["p/B"], &["p/A"]));
+    analysis.sources.insert("c.scala".into(), source("3", &["p/C"], &["p/B"]));
+    analysis.sources.insert("d.scala".into(), source("4", &["p/D"], &[]));
+    analysis
+  }
+
+  fn hashes(entries: &[(&str, &str)]) -> BTreeMap<PathBuf, String> {
+    entries.iter().map(|(p, h)| (PathBuf::from(p), h.to_string())).collect()
+  }
+
+  fn paths(paths: &[&str]) -> BTreeSet<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+  }
+
+  #[test]
+  fn unchanged() {
+    let invalidation = analysis().invalidate(&hashes(&[("a.scala", "1"), ("b.scala", "2"), ("c.scala", "3"), ("d.scala", "4")]));
+    assert!(invalidation.is_empty());
+  }
+
+  #[test]
+  fn changed_with_dependents() {
+    let invalidation = analysis().invalidate(&hashes(&[("a.scala", "x"), ("b.scala", "2"), ("c.scala", "3"), ("d.scala", "4")]));
+    assert_eq!(invalidation.compile, paths(&["a.scala", "b.scala", "c.scala"]));
+    assert!(invalidation.removed.is_empty());
+    let invalidation = analysis().invalidate(&hashes(&[("a.scala", "1"), ("b.scala", "2"), ("c.scala", "3"), ("d.scala", "x")]));
+    assert_eq!(invalidation.compile, paths(&["d.scala"]));
+  }
+
+  #[test]
+  fn removed_and_new() {
+    let invalidation = analysis().invalidate(&hashes(&[("b.scala", "2"), ("c.scala", "3"), ("d.scala", "4"), ("e.scala", "5")]));
+    assert_eq!(invalidation.compile, paths(&["b.scala", "c.scala", "e.scala"]));
+    assert_eq!(invalidation.removed, paths(&["a.scala"]));
+  }
+
+  #[test]
+  fn update_from_report() {
+    let hashes = hashes(&[("a.scala", "x"), ("b.scala", "2"), ("c.scala", "3")]);
+    let report = "source a.scala\ndefine p/A\ndefine p/A2\nuse scala/Int\nsource b.scala\ndefine p/B\nuse p/A\n";
+    let updated = analysis().update(&hashes, &paths(&["a.scala", "b.scala"]), report).expect("every source reported");
+    assert_eq!(updated.sources.keys().cloned().collect::<BTreeSet<_>>(), paths(&["a.scala", "b.scala", "c.scala"]));
+    assert_eq!(updated.sources[Path::new("a.scala")], source("x", &["p/A", "p/A2"], &["scala/Int"]));
+    assert_eq!(updated.sources[Path::new("c.scala")], source("3", &["p/C"], &["p/B"]));
+    assert!(analysis().update(&hashes, &paths(&["a.scala", "c.scala"]), report).is_none());
+  }
+
+  #[test]
+  fn remove_classes() {
+    let dir = utils::test_dir("incremental");
+    std::fs::create_dir_all(dir.join("p")).unwrap();
+    for name in &["A.class", "A$.class", "A$Inner.class", "AB.class", "B.class"] {
+      std::fs::write(dir.join("p").join(name), b"").unwrap();
+    }
+    analysis().remove_classes(&dir, &paths(&["a.scala"])).unwrap();
+    let mut left = std::fs::read_dir(dir.join("p")).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect::<Vec<_>>();
+    left.sort();
+    assert_eq!(left, vec!["AB.class", "B.class"]);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
@@ -29,6 +29,8 @@ edition = "<edition>"
 const INGORE_CONTENT: &str = r#"
 /target
 .metals
+.bloop
+.bsp
 "#;
 
 const HELLO_CODE: &str = r#"
@@ -63,8 +65,12 @@ pub fn build_template(name: &str, edition: &str) -> String {
 pub fn main(opts: Opts) -> Result<(), anyhow::Error> {
   let name = match opts.name {
     Some(name) => name,
-    None => std::env::current_dir()?.file_name().ok_or_else(|| anyhow::Error::msg("current dir is root"))?.to_string_lossy().to_string(),
+    // `my-app` is a fine folder but not a package name
+    None => std::env::current_dir()?.file_name().ok_or_else(|| anyhow::Error::msg("current dir is root"))?.to_string_lossy().replace('-', "_"),
   };
+  if !crate::config::is_ident(&name) {
+    return Err(anyhow::Error::msg(format!("{:?} is not a valid scala identifier, set a name with --name", name)))
+  }
   info!("init project {}", name);
   let mut toml_file = OpenOptions::new().write(true).create_new(true).open(toml_name())?;
   toml_file.write_all(build_template(&name, &scala_edition()).as_bytes())?;
@@ -73,6 +79,7 @@ pub fn main(opts: Opts) -> Result<(), anyhow::Error> {
   if let Ok(mut ignore_file) = OpenOptions::new().write(true).create_new(true).open(".gitignore") {
     ignore_file.write_all(INGORE_CONTENT.trim_start().as_bytes())?;
   }
+  crate::bsp::write_connection_file()?;
   create_dir_all("src").ok();
   if let Ok(mut src_file) = OpenOptions::new().write(true).create_new(true).open("src/main.scala") {
     src_file.write_all(HELLO_CODE.trim_start().as_bytes())?;
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::Context;
+use crate::{build, dist};
+use crate::build::{TargetName, Profile};
+use crate::config::constant::*;
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  /// project to install, current dir by default
+  #[clap(long)]
+  pub path: Option<PathBuf>,
+  /// list installed packages instead
+  #[clap(long)]
+  pub list: bool,
+}
+
+#[derive(Clap)]
+pub struct UninstallOpts {
+  pub name: String,
+}
+
+/// an entry in `~/.carsier/installs.json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Installed {
+  pub version: String,
+  pub source: PathBuf,
+  pub main_class: String,
+}
+
+fn manifest_path() -> PathBuf {
+  home_dir().join("installs.json")
+}
+
+fn lib_dir(name: &str) -> PathBuf {
+  home_dir().join("lib").join(name)
+}
+
+fn bin_path(name: &str) -> PathBuf {
+  home_dir().join("bin").join(name)
+}
+
+fn load_manifest() -> Result<BTreeMap<String, Installed>, anyhow::Error> {
+  match utils::load_content(manifest_path())? {
+    Some(content) => serde_json::from_str(&content).context("read installs.json"),
+    None => Ok(BTreeMap::new()),
+  }
+}
+
+fn save_manifest(manifest: &BTreeMap<String, Installed>) -> Result<(), anyhow::Error> {
+  let _ = utils::compare_and_write(manifest_path(), serde_json::to_string_pretty(manifest)?.as_bytes())?;
+  Ok(())
+}
+
+/// build the bin target of the project in release profile for `edition`, the default one if not set,
+/// then copy its jars into `~/.carsier/lib/<name>` with a launcher in `~/.carsier/bin`
+pub fn main(mut opts: Opts, config_path: Option<PathBuf>, edition: Option<String>) -> Result<(), anyhow::Error> {
+  let mut manifest = load_manifest()?;
+  if opts.list {
+    for (name, installed) in &manifest {
+      println!("{} v{} ({})", name, installed.version, installed.source.display());
+    }
+    return Ok(())
+  }
+  if let Some(path) = &opts.path {
+    std::env::set_current_dir(path).with_context(|| format!("chdir {}", path.display()))?;
+  }
+  let config = crate::load_config(config_path.unwrap_or_else(|| toml_name().into())).context("load config")?;
+  let config = config.select_edition(edition.as_ref().unwrap_or(&config.package.edition))?;
+  std::fs::create_dir_all(config.target_dir())?;
+  opts.build.target.release = true;
+  opts.build.target.targets = vec![TargetName::BinMain.to_string()];
+  build::main(opts.build, &config).context("build failed")?;
+  let name = &config.package.name;
+  let main_class = build::main_class(&TargetName::BinMain, &config).expect("bin target has main class");
+  let lib_dir = lib_dir(name);
+  let _ = std::fs::remove_dir_all(&lib_dir);
+  dist::copy_jars(&lib_dir, &config, Profile::Release)?;
+  let bin = bin_path(name);
+  let _ = utils::compare_and_write(&bin, dist::launcher(&lib_dir.display().to_string(), &main_class, &config.package.jvm.launcher_options()).as_bytes())?;
+  dist::set_executable(&bin)?;
+  manifest.insert(name.clone(), Installed {
+    version: config.package.version.clone(),
+    source: std::env::current_dir()?,
+    main_class,
+  });
+  save_manifest(&manifest)?;
+  info!("installed {} v{} => {}", name, config.package.version, bin.display());
+  Ok(())
+}
+
+pub fn uninstall(opts: UninstallOpts) -> Result<(), anyhow::Error> {
+  let mut manifest = load_manifest()?;
+  if manifest.remove(&opts.name).is_none() {
+    return Err(anyhow::Error::msg(format!("{} is not installed", opts.name)))
+  }
+  let _ = std::fs::remove_dir_all(lib_dir(&opts.name));
+  let _ = std::fs::remove_file(bin_path(&opts.name));
+  save_manifest(&manifest)?;
+  info!("uninstalled {}", opts.name);
+  Ok(())
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use crate::utils;
+
+/// entries of `META-INF/MANIFEST.MF`, kept in insertion order
+#[derive(Debug, Clone)]
+pub struct Manifest(Vec<(String, String)>);
+
+impl Default for Manifest {
+  fn default() -> Self {
+    Self(vec![
+      ("Manifest-Version".to_string(), "1.0".to_string()),
+      ("Created-By".to_string(), crate::config::constant::NAME.to_string()),
+    ])
+  }
+}
+
+impl Manifest {
+  pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut Self {
+    let (key, value) = (key.into(), value.into());
+    match self.0.iter_mut().find(|(k, _)| k == &key) {
+      Some(entry) => entry.1 = value,
+      None => self.0.push((key, value)),
+    }
+    self
+  }
+
+  /// lines are wrapped at 72 bytes as the jar spec requires
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut result = Vec::new();
+    for (key, value) in &self.0 {
+      let line = format!("{}: {}", key, value);
+      let mut line = line.as_bytes();
+      let mut first = true;
+      while !line.is_empty() {
+        let width = if first { 72 } else { 71 };
+        let mut n = line.len().min(width);
+        while n < line.len() && (line[n] & 0xC0) == 0x80 {
+          n -= 1;
+        }
+        if !first {
+          result.push(b' ');
+        }
+        result.extend_from_slice(&line[..n]);
+        result.extend_from_slice(b"\r\n");
+        line = &line[n..];
+        first = false;
+      }
+    }
+    result.extend_from_slice(b"\r\n");
+    result
+  }
+}
+
+/// collect entries in memory, then write them in a deterministic order with fixed timestamps,
+/// so the same inputs always produce the same jar byte-for-byte
+#[derive(Debug, Default)]
+pub struct JarBuilder {
+  manifest: Manifest,
+  entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl JarBuilder {
+  pub fn new(manifest: Manifest) -> Self {
+    Self { manifest, entries: BTreeMap::new() }
+  }
+
+  pub fn add<S: Into<String>>(&mut self, name: S, content: Vec<u8>) -> &mut Self {
+    self.entries.insert(name.into(), content);
+    self
+  }
+
+  pub fn add_file<P: AsRef<Path>, S: Into<String>>(&mut self, path: P, name: S) -> Result<&mut Self, anyhow::Error> {
+    let content = utils::load_content_raw(&path)?.ok_or_else(|| anyhow::Error::msg(format!("open {} failed", path.as_ref().display())))?;
+    Ok(self.add(name, content))
+  }
+
+  /// add all files under `dir`, placed under `prefix` in the jar
+  pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P, prefix: &str) -> Result<&mut Self, anyhow::Error> {
+    for entry in std::fs::read_dir(dir)? {
+      let entry = entry?;
+      let name = join_entry(prefix, &entry.file_name().to_string_lossy());
+      if entry.file_type()?.is_dir() {
+        self.add_dir(entry.path(), &name)?;
+      } else {
+        self.add_file(entry.path(), name)?;
+      }
+    }
+    Ok(self)
+  }
+
+  /// hash of every entry, changes iff the written jar would change
+  pub fn fingerprint(&self) -> String {
+    let mut result = format!("MANIFEST {:016x}\n", utils::hash_content(&self.manifest.to_bytes()));
+    for (name, content) in &self.entries {
+      result += &format!("{} {:016x}\n", name, utils::hash_content(content));
+    }
+    result
+  }
+
+  pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+    use zip::write::SimpleFileOptions;
+    let options = SimpleFileOptions::default()
+      .compression_method(zip::CompressionMethod::Deflated)
+      .last_modified_time(zip::DateTime::default())
+      .unix_permissions(0o644);
+    let dir_options = options.unix_permissions(0o755);
+    let mut dirs = std::collections::BTreeSet::new();
+    for name in self.entries.keys() {
+      let mut parts = name.split('/').collect::<Vec<_>>();
+      parts.pop();
+      for i in 1..=parts.len() {
+        dirs.insert(format!("{}/", parts[..i].join("/")));
+      }
+    }
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    writer.add_directory("META-INF/", dir_options)?;
+    writer.start_file("META-INF/MANIFEST.MF", options)?;
+    writer.write_all(&self.manifest.to_bytes())?;
+    for dir in dirs.iter().filter(|d| d.as_str() != "META-INF/") {
+      writer.add_directory(dir.as_str(), dir_options)?;
+    }
+    for (name, content) in self.entries.iter().filter(|(name, _)| name.as_str() != "META-INF/MANIFEST.MF") {
+      writer.start_file(name.as_str(), options)?;
+      writer.write_all(content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+  }
+
+  pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<utils::FileDep, anyhow::Error> {
+    utils::compare_and_write(path, &self.to_bytes()?)
+  }
+}
+
+pub fn join_entry(prefix: &str, name: &str) -> String {
+  let prefix = prefix.trim_matches('/');
+  if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) }
+}
+
+/// jar entry name of a relative path, always separated by `/`
+pub fn entry_name(path: &Path) -> String {
+  path.components().filter(|c| matches!(c, std::path::Component::Normal(_))).map(|c| c.as_os_str().to_string_lossy().to_string()).collect::<Vec<_>>().join("/")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(jar: &[u8]) -> Vec<String> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(jar)).unwrap();
+    (0..archive.len()).map(|i| archive.by_index(i).unwrap().name().to_string()).collect()
+  }
+
+  #[test]
+  fn deterministic_entries() {
+    let mut a = JarBuilder::new(Manifest::default());
+    a.add("b/c/D.class", vec![1]).add("A.class", vec![2]);
+    let mut b = JarBuilder::new(Manifest::default());
+    b.add("A.class", vec![2]).add("b/c/D.class", vec![1]);
+    let bytes = a.to_bytes().unwrap();
+    assert_eq!(bytes, b.to_bytes().unwrap());
+    assert_eq!(names(&bytes), vec!["META-INF/", "META-INF/MANIFEST.MF", "b/", "b/c/", "A.class", "b/c/D.class"]);
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(&bytes)).unwrap();
+    for i in 0..archive.len() {
+      let entry = archive.by_index(i).unwrap();
+      let mode = entry.unix_mode().unwrap() & 0o777;
+      assert_eq!(mode, if entry.is_dir() { 0o755 } else { 0o644 }, "{}", entry.name());
+    }
+  }
+
+  #[test]
+  fn fingerprint_follows_content() {
+    let jar = |content: u8, version: &str| {
+      let mut manifest = Manifest::default();
+      manifest.set("Implementation-Version", version);
+      let mut jar = JarBuilder::new(manifest);
+      jar.add("A.class", vec![content]);
+      jar.fingerprint()
+    };
+    assert_eq!(jar(1, "0.1.0"), jar(1, "0.1.0"));
+    assert_ne!(jar(1, "0.1.0"), jar(2, "0.1.0"));
+    assert_ne!(jar(1, "0.1.0"), jar(1, "0.2.0"));
+  }
+
+  #[test]
+  fn manifest_lines_are_wrapped() {
+    let mut manifest = Manifest::default();
+    manifest.set("Class-Path", "a".repeat(100));
+    let content = String::from_utf8(manifest.to_bytes()).unwrap();
+    let lines = content.split("\r\n").collect::<Vec<_>>();
+    assert_eq!(lines[..2], ["Manifest-Version: 1.0", "Created-By: carsier"]);
+    assert_eq!(lines[2], format!("Class-Path: {}", "a".repeat(60)));
+    assert_eq!(lines[3], format!(" {}", "a".repeat(40)));
+    assert!(lines.iter().all(|l| l.len() <= 72));
+    assert!(content.ends_with("\r\n\r\n"));
+  }
+
+  #[test]
+  fn entry_names() {
+    assert_eq!(entry_name(Path::new("./a/b/C.scala")), "a/b/C.scala");
+    assert_eq!(join_entry("/res/", "a.txt"), "res/a.txt");
+    assert_eq!(join_entry("", "a.txt"), "a.txt");
+  }
+}
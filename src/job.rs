@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+
+pub type JobId = usize;
+
+type JobFn<'a> = Box<dyn FnOnce() -> Result<(), anyhow::Error> + Send + 'a>;
+
+struct Job<'a> {
+  name: String,
+  deps: Vec<JobId>,
+  run: JobFn<'a>,
+}
+
+/// a job could only depend on jobs added before it, so the graph is always a DAG
+#[derive(Default)]
+pub struct Scheduler<'a> {
+  jobs: Vec<Job<'a>>,
+}
+
+impl<'a> Scheduler<'a> {
+  pub fn new() -> Self {
+    Self { jobs: Vec::new() }
+  }
+
+  pub fn add<S, F>(&mut self, name: S, deps: &[JobId], f: F) -> JobId
+    where S: Into<String>, F: FnOnce() -> Result<(), anyhow::Error> + Send + 'a {
+    let id = self.jobs.len();
+    assert!(deps.iter().all(|&d| d < id), "job depends on unknown job");
+    self.jobs.push(Job { name: name.into(), deps: deps.to_vec(), run: Box::new(f) });
+    id
+  }
+
+  /// run jobs with at most `parallel` of them at the same time,
+  /// stop spawning new jobs after the first failure
+  pub fn run(self, parallel: usize) -> Result<(), anyhow::Error> {
+    let parallel = parallel.max(1);
+    let total = self.jobs.len();
+    let names = self.jobs.iter().map(|j| j.name.clone()).collect::<Vec<_>>();
+    let mut pending = self.jobs.into_iter().map(|j| Some((j.deps, j.run))).collect::<Vec<_>>();
+    let mut running = BTreeSet::new();
+    let mut finished = BTreeSet::new();
+    let mut error: Option<anyhow::Error> = None;
+    let progress = Progress(std::io::stderr().is_terminal());
+    std::thread::scope(|scope| {
+      let (tx, rx) = std::sync::mpsc::channel();
+      loop {
+        if error.is_none() {
+          for id in 0..total {
+            if running.len() >= parallel {
+              break
+            }
+            if !matches!(&pending[id], Some((deps, _)) if deps.iter().all(|d| finished.contains(d))) {
+              continue
+            }
+            let (_, run) = pending[id].take().expect("pending job");
+            debug!("job started: {}", names[id]);
+            running.insert(id);
+            let tx = tx.clone();
+            scope.spawn(move || {
+              let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run))
+                .unwrap_or_else(|_| Err(anyhow::Error::msg("job panicked")));
+              let _ = tx.send((id, result));
+            });
+          }
+        }
+        if running.is_empty() {
+          break
+        }
+        progress.show(finished.len(), total, running.iter().map(|&i| names[i].as_str()));
+        let (id, result) = rx.recv().expect("job channel closed");
+        running.remove(&id);
+        match result {
+          Ok(()) => {
+            debug!("job finished: {}", names[id]);
+            finished.insert(id);
+          },
+          Err(e) => {
+            error.get_or_insert(e.context(format!("job {} failed", names[id])));
+          },
+        }
+      }
+    });
+    progress.clear();
+    match error {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
+}
+
+/// the progress line shown on stderr, taken off while a log record is written
+static PROGRESS_LINE: Mutex<Option<String>> = Mutex::new(None);
+
+struct Progress(bool);
+impl Progress {
+  fn show<'s, I: Iterator<Item = &'s str>>(&self, finished: usize, total: usize, running: I) {
+    if self.0 {
+      let mut shown = PROGRESS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+      let line = format!("[{}/{}] {}", finished, total, running.collect::<Vec<_>>().join(", "));
+      eprint!("\r\x1b[K{}", line);
+      let _ = std::io::stderr().flush();
+      *shown = Some(line);
+    }
+  }
+  fn clear(&self) {
+    if self.0 && PROGRESS_LINE.lock().unwrap_or_else(|e| e.into_inner()).take().is_some() {
+      eprint!("\r\x1b[K");
+    }
+  }
+}
+
+/// logs to the terminal through the inner logger, clearing the progress line
+/// of a running scheduler before each record and drawing it again after
+pub struct ProgressLogger(pub Box<dyn simplelog::SharedLogger>);
+
+impl log::Log for ProgressLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    self.0.enabled(metadata)
+  }
+
+  fn log(&self, record: &log::Record) {
+    if !self.0.enabled(record.metadata()) {
+      return
+    }
+    let shown = PROGRESS_LINE.lock().unwrap_or_else(|e| e.into_inner());
+    if shown.is_some() {
+      eprint!("\r\x1b[K");
+    }
+    self.0.log(record);
+    self.0.flush();
+    if let Some(line) = shown.as_ref() {
+      eprint!("{}", line);
+      let _ = std::io::stderr().flush();
+    }
+  }
+
+  fn flush(&self) {
+    self.0.flush()
+  }
+}
+
+impl simplelog::SharedLogger for ProgressLogger {
+  fn level(&self) -> log::LevelFilter {
+    self.0.level()
+  }
+
+  fn config(&self) -> Option<&simplelog::Config> {
+    self.0.config()
+  }
+
+  fn as_log(self: Box<Self>) -> Box<dyn log::Log> {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Scheduler;
+  use std::sync::Mutex;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn dependencies_run_first() {
+    let order = Mutex::new(Vec::new());
+    let mut jobs = Scheduler::new();
+    let push = |name: &'static str| { let order = &order; move || { order.lock().unwrap().push(name); Ok(()) } };
+    let a = jobs.add("a", &[], push("a"));
+    let b = jobs.add("b", &[a], push("b"));
+    let c = jobs.add("c", &[a], push("c"));
+    jobs.add("d", &[b, c], push("d"));
+    jobs.run(4).unwrap();
+    let order = order.into_inner().unwrap();
+    let pos = |name| order.iter().position(|&n| n == name).unwrap();
+    assert_eq!(order.len(), 4);
+    assert!(pos("a") < pos("b") && pos("a") < pos("c"));
+    assert!(pos("b") < pos("d") && pos("c") < pos("d"));
+  }
+
+  #[test]
+  fn at_most_parallel() {
+    let (running, max) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let mut jobs = Scheduler::new();
+    for i in 0..8 {
+      let (running, max) = (&running, &max);
+      jobs.add(format!("job{}", i), &[], move || {
+        max.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        running.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+      });
+    }
+    jobs.run(2).unwrap();
+    assert!(max.load(Ordering::SeqCst) <= 2);
+  }
+
+  #[test]
+  fn failure_stops_dependents() {
+    let ran = Mutex::new(Vec::new());
+    let mut jobs = Scheduler::new();
+    let a = jobs.add("a", &[], || Err(anyhow::Error::msg("boom")));
+    jobs.add("b", &[a], || { ran.lock().unwrap().push("b"); Ok(()) });
+    jobs.add("c", &[], || { ran.lock().unwrap().push("c"); Ok(()) });
+    let e = jobs.run(1).unwrap_err();
+    assert_eq!(format!("{:#}", e), "job a failed: boom");
+    // nothing is spawned after the first failure
+    assert!(ran.into_inner().unwrap().is_empty());
+  }
+
+  #[test]
+  fn panic_is_an_error() {
+    let mut jobs = Scheduler::new();
+    jobs.add("p", &[], || panic!("oops"));
+    assert_eq!(format!("{:#}", jobs.run(1).unwrap_err()), "job p failed: job panicked");
+  }
+}
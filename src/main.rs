@@ -12,6 +12,23 @@ mod utils;
 mod resolve;
 mod preprocess;
 mod build;
+mod server;
+mod incremental;
+mod job;
+mod jar;
+mod assembly;
+mod edit;
+mod clean;
+mod doc;
+mod console;
+mod publish;
+mod dist;
+mod install;
+mod metadata;
+mod ide;
+mod bsp;
+mod watch;
+mod toolchain;
 
 use config::{PackageConfig, repo::RepoConfig};
 use utils::ResultLog;
@@ -24,6 +41,12 @@ pub struct Opts {
   config: Option<PathBuf>,
   #[clap(short = "v", long)]
   verbose: bool,
+  /// scala edition to build, one of `editions` in `[package]`
+  #[clap(long, global = true)]
+  edition: Option<String>,
+  /// build or check every edition in `editions` of `[package]`
+  #[clap(long, global = true, conflicts_with = "edition")]
+  all_editions: bool,
   #[clap(subcommand)]
   subcmd: SubCommand,
 }
@@ -37,8 +60,24 @@ pub struct ExternelOpts {
 pub enum SubCommand {
   New(init::NewOpts),
   Init(init::Opts),
-  Build(build::Opts),
+  Build(watch::Opts),
+  Check(watch::Opts),
   Resolve(resolve::Opts),
+  Tree(resolve::TreeOpts),
+  Server(server::Opts),
+  Assembly(assembly::Opts),
+  Add(edit::AddOpts),
+  Clean(clean::Opts),
+  Publish(publish::Opts),
+  Package(dist::Opts),
+  Install(install::Opts),
+  Metadata(metadata::Opts),
+  Ide(ide::Opts),
+  Bsp(bsp::Opts),
+  Uninstall(install::UninstallOpts),
+  Console(console::Opts),
+  Doc(doc::Opts),
+  Remove(edit::RemoveOpts),
   // TODO: https://github.com/clap-rs/clap/issues/1672
   // #[clap(external_subcommand)]
   // External(Vec<String>),
@@ -57,13 +96,23 @@ fn load_config<P: AsRef<Path>>(path: P) -> Result<PackageConfig, anyhow::Error>
   let toml_str = utils::load_content(path)?.ok_or_else(|| anyhow::Error::msg("open config file"))?;
   let config: PackageConfig = toml::from_str(&toml_str)?;
   dbg!(&config);
+  config.validate()?;
   Ok(config)
 }
 
 fn init_logger(verbose: bool, path: Option<&Path>) {
+  init_logger_mode(verbose, path, Some(simplelog::TerminalMode::Mixed))
+}
+
+/// `TerminalMode::Stderr` keeps stdout clean for machine readable output,
+/// `None` logs to the file only
+fn init_logger_mode(verbose: bool, path: Option<&Path>, mode: Option<simplelog::TerminalMode>) {
   use simplelog::*;
   let level = if verbose { LevelFilter::Debug } else { LevelFilter::Info };
-  let mut loggers: Vec<Box<(dyn SharedLogger)>> =  vec![ TermLogger::new(level, Config::default(), TerminalMode::Mixed).unwrap(), ];
+  let mut loggers: Vec<Box<(dyn SharedLogger)>> = match mode {
+    Some(mode) => vec![ Box::new(job::ProgressLogger(TermLogger::new(level, Config::default(), mode).unwrap())), ],
+    None => vec![],
+  };
   if let Some(path) = path {
     if let Ok(file) = std::fs::File::create(path) {
       loggers.push(WriteLogger::new(LevelFilter::Info, Config::default(), file))
@@ -79,6 +128,19 @@ fn init_logger(verbose: bool, path: Option<&Path>) {
   CombinedLogger::init(loggers).unwrap();
 }
 
+/// editions the subcommand runs for, only build and check run for more than one
+fn select_editions(edition: Option<String>, all_editions: bool, subcmd: &SubCommand, config: &PackageConfig) -> Result<Vec<String>, anyhow::Error> {
+  let editions = match (edition, all_editions) {
+    (_, true) => config.editions(),
+    (Some(edition), false) => vec![edition],
+    (None, false) => vec![config.package.edition.clone()],
+  };
+  if editions.len() > 1 && !matches!(subcmd, SubCommand::Build(_) | SubCommand::Check(_)) {
+    return Err(anyhow::Error::msg("--all-editions only works with build and check"))
+  }
+  Ok(editions)
+}
+
 fn main() {
   let opts: Opts = Opts::parse();
   let verbose = opts.verbose;
@@ -97,6 +159,20 @@ fn main() {
       init::main(sub_opts.opts).expect("execute failed");
       return
     },
+    SubCommand::Install(sub_opts) => {
+      init_logger(verbose, None);
+      if opts.all_editions {
+        error!("--all-editions only works with build and check");
+        return
+      }
+      install::main(sub_opts, opts.config, opts.edition).expect("execute failed");
+      return
+    },
+    SubCommand::Uninstall(sub_opts) => {
+      init_logger(verbose, None);
+      install::uninstall(sub_opts).expect("execute failed");
+      return
+    },
     SubCommand::External(ExternelOpts { args }) => {
       let cmd = format!("{}-{}", config::constant::NAME, args.first().expect("expect external command"));
       utils::call(&cmd, &args[1..]).unwrap_or_else(|e| panic!("external sub-command {} failed: {:?}", cmd, e));
@@ -105,17 +181,114 @@ fn main() {
     subcmd => subcmd,
   };
   // load_repo_config("../configs/repo.toml").unwrap();
-  let config = load_config(opts.config.unwrap_or_else(|| config::constant::toml_name().into())).expect("load config");
+  let config_path = opts.config.unwrap_or_else(|| config::constant::toml_name().into());
+  let config = load_config(&config_path).expect("load config");
+  let selected = select_editions(opts.edition, opts.all_editions, &subcmd, &config)
+    .and_then(|editions| Ok((config.select_edition(&editions[0])?, editions)));
+  let (config, editions) = match selected {
+    Ok(selected) => selected,
+    Err(e) => {
+      init_logger(verbose, None);
+      error!("{}", e);
+      return
+    },
+  };
   std::fs::create_dir_all("target").expect("create target dir");
+  // `carsier clean` takes it exclusively
+  let _running = match subcmd {
+    SubCommand::Clean(_) => None,
+    _ => utils::target_lock().and_then(|lock| Ok(lock.lock_shared().map(|_| lock)?)).ok_or_warn(),
+  };
   match subcmd {
-    SubCommand::Init(_) | SubCommand::New(_) | SubCommand::External(_) => unreachable!("already handled"),
+    SubCommand::Init(_) | SubCommand::New(_) | SubCommand::External(_) |
+    SubCommand::Install(_) | SubCommand::Uninstall(_) => unreachable!("already handled"),
     SubCommand::Resolve(opts) => {
       init_logger(verbose, Some("target/resolve.log".as_ref()));
       resolve::main(opts, &config).ok_or_error();
     },
+    SubCommand::Tree(opts) => {
+      init_logger_mode(verbose, Some("target/resolve.log".as_ref()), Some(simplelog::TerminalMode::Stderr));
+      resolve::tree(opts, &config).ok_or_error();
+    },
     SubCommand::Build(opts) => {
       init_logger(verbose, Some("target/build.log".as_ref()));
-      build::main(opts, &config).ok_or_error();
+      watch::main(opts, &config_path, config, |opts, config| {
+        for edition in &editions {
+          let config = config.select_edition(edition)?;
+          if editions.len() > 1 {
+            info!("edition {}", edition);
+          }
+          let coursier = opts.resolve.coursier.clone();
+          let features = opts.resolve.features.clone();
+          build::main(opts.clone(), &config)?;
+          ide::refresh(&coursier, &features, &config).ok_or_warn();
+        }
+        Ok(())
+      }).ok_or_error();
+    },
+    SubCommand::Check(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      watch::main(opts, &config_path, config, |opts, config| {
+        for edition in &editions {
+          let config = config.select_edition(edition)?;
+          if editions.len() > 1 {
+            info!("edition {}", edition);
+          }
+          let coursier = opts.resolve.coursier.clone();
+          let features = opts.resolve.features.clone();
+          build::check(opts.clone(), &config)?;
+          ide::refresh(&coursier, &features, &config).ok_or_warn();
+        }
+        Ok(())
+      }).ok_or_error();
+    },
+    SubCommand::Bsp(opts) => {
+      init_logger_mode(verbose, Some("target/bsp.log".as_ref()), None);
+      bsp::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Ide(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      ide::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Doc(opts) => {
+      init_logger(verbose, Some("target/doc.log".as_ref()));
+      doc::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Console(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      console::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Publish(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      publish::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Package(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      dist::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Metadata(opts) => {
+      init_logger_mode(verbose, Some("target/metadata.log".as_ref()), Some(simplelog::TerminalMode::Stderr));
+      metadata::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Assembly(opts) => {
+      init_logger(verbose, Some("target/build.log".as_ref()));
+      assembly::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Add(opts) => {
+      init_logger(verbose, None);
+      edit::add(opts, &config_path, &config).ok_or_error();
+    },
+    SubCommand::Remove(opts) => {
+      init_logger(verbose, None);
+      edit::remove(opts, &config_path).ok_or_error();
+    },
+    SubCommand::Clean(opts) => {
+      init_logger(verbose, None);
+      clean::main(opts, &config).ok_or_error();
+    },
+    SubCommand::Server(opts) => {
+      init_logger(verbose, None);
+      server::main(opts, &config).ok_or_error();
     }
   }
 }
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::Context;
+use crate::{build, resolve, preprocess};
+use crate::config::{PackageConfig, Package};
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  #[clap(long, default_value = "1")]
+  pub format_version: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Metadata<'a> {
+  format_version: u32,
+  package: &'a Package,
+  targets: Vec<TargetMetadata>,
+  features: Vec<String>,
+  modules: BTreeMap<String, Vec<preprocess::Unit>>,
+  dependencies: Vec<DependencyMetadata>,
+  resolved: Vec<ResolvedMetadata>,
+  outputs: BTreeMap<&'static str, PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct TargetMetadata {
+  name: String,
+  profile: String,
+  features: Vec<String>,
+  main_class: Option<String>,
+  classes: PathBuf,
+  jar: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyMetadata {
+  name: String,
+  org: Option<String>,
+  java: bool,
+  version: String,
+  features: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ResolvedMetadata {
+  org: String,
+  name: String,
+  version: String,
+  jar: Option<PathBuf>,
+}
+
+/// coordinates from `deps.out`, matched with jars in `deps.classpath` by file name
+fn resolved(config: &PackageConfig) -> Result<Vec<ResolvedMetadata>, anyhow::Error> {
+  let jars = resolve::classpath(config)?;
+  let deps_out = utils::load_content(config.target_dir().join("deps.out"))?.unwrap_or_default();
+  Ok(deps_out.lines().filter_map(|line| {
+    let mut parts = line.trim().split(':');
+    let (org, name, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let filename = format!("{}-{}.jar", name, version);
+    let jar = jars.iter().find(|j| j.file_name().map(|f| f.to_string_lossy() == filename).unwrap_or(false)).cloned();
+    Some(ResolvedMetadata { org: org.to_string(), name: name.to_string(), version: version.to_string(), jar })
+  }).collect())
+}
+
+/// resolve and preprocess, then print everything tools need to know as json
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  if opts.format_version != 1 {
+    return Err(anyhow::Error::msg(format!("unsupported format version {}", opts.format_version)))
+  }
+  let targets = build::get_target(&opts.build, config).context("parse target failed")?;
+  let features = opts.build.resolve.features.clone();
+  resolve::main(opts.build.resolve, config).context("resolve failed")?;
+  preprocess::main(opts.build.preprocess, config).context("preprocess failed")?;
+  let targets = targets.into_iter().map(|target| TargetMetadata {
+    name: target.name.to_string(),
+    profile: format!("{:?}", target.profile).to_lowercase(),
+    features: target.features.keys().cloned().collect(),
+    main_class: build::main_class(&target.name, config),
+    classes: build::classes_dir(&target.name, target.profile, config),
+    jar: build::jar_path(&target.name, target.profile, config),
+  }).collect();
+  let dependencies = config.dependencies.iter().map(|(name, dep)| {
+    let dep = dep.as_dep();
+    DependencyMetadata {
+      name: name.clone(),
+      org: dep.org.clone(),
+      java: dep.java,
+      version: dep.version.to_string(),
+      features: dep.features.clone(),
+    }
+  }).collect();
+  let outputs = vec![
+    ("target", config.target_dir()),
+    ("build", build::Profile::Debug.build_dir(config)),
+    ("release", build::Profile::Release.build_dir(config)),
+    ("doc", config.target_dir().join("doc").join(&config.package.name)),
+    ("dist", config.target_dir().join("dist")),
+    ("assembly", config.target_dir().join("assembly")),
+    ("classpath", resolve::Scope::Compile.file("classpath", config)),
+    ("dev-classpath", resolve::Scope::Dev.file("classpath", config)),
+    ("runtime-classpath", resolve::Scope::Runtime.file("classpath", config)),
+  ].into_iter().collect();
+  let metadata = Metadata {
+    format_version: opts.format_version,
+    package: &config.package,
+    targets,
+    features,
+    modules: build::load_units(config)?,
+    dependencies,
+    resolved: resolved(config)?,
+    outputs,
+  };
+  println!("{}", serde_json::to_string_pretty(&metadata)?);
+  Ok(())
+}
@@ -1,17 +1,14 @@
 use std::collections::BTreeSet;
 use crate::config::PackageConfig;
 use crate::utils;
-use crate::config::constant::*;
 use crate::build::Target;
 use anyhow::Context;
 
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
-#[derive(Clap)]
+#[derive(Clap, Clone)]
 pub struct Opts {
-  #[clap(long)]
-  pub features: Vec<String>,
   #[clap(long="include", default_value="src/**/*.scala")]
   pub include: String,
   #[clap(long="src-root")]
@@ -141,16 +138,14 @@ impl ToString for Prefix {
   }
 }
 
-fn preprocess(mods: &mut BTreeMap<Mod, Vec<Unit>>, pattern: &str, root: &Path, crate_name: &str, registry_name: &str) -> Result<(), anyhow::Error> {
+fn preprocess(mods: &mut BTreeMap<Mod, Vec<Unit>>, pattern: &str, root: &Path, crate_name: &str, registry_name: &str, target_dir: &Path) -> Result<(), anyhow::Error> {
   use std::io::Write;
   for path in glob::glob(pattern).context("pattern not valid")?.filter_map(|i| i.ok()) {
     let (current, features) = Mod::from_path(&path, root);
     // let current = ();
     if let Some(content) = utils::load_content(&path)? {
-      let out_path = target_dir().join(&path);
-      std::fs::create_dir_all(out_path.parent().expect("parent"))?;
-      let mut fout = std::fs::File::create(&out_path)?;
-      info!("transform: {} => {}", path.display(), out_path.display());
+      let out_path = target_dir.join(&path);
+      let mut fout = Vec::new();
       let mut multicomments = false;
       let mut actual_current = None;
       for line in content.lines() {
@@ -194,6 +189,10 @@ fn preprocess(mods: &mut BTreeMap<Mod, Vec<Unit>>, pattern: &str, root: &Path, c
         }
         writeln!(fout, "{}", line)?;
       }
+      // unchanged outputs keep their mtime, so watchers and incremental steps skip them
+      if let utils::FileDep::Touched = utils::compare_and_write(&out_path, &fout)? {
+        info!("transform: {} => {}", path.display(), out_path.display());
+      }
       if let Some(current) = actual_current {
         mods.entry(current).or_default().push(Unit{ path, features });
       }
@@ -205,21 +204,21 @@ fn preprocess(mods: &mut BTreeMap<Mod, Vec<Unit>>, pattern: &str, root: &Path, c
 pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
   let src_root = opts.src_root.clone().unwrap_or_else(|| opts.include.split('/').take_while(|s| !s.contains('*')).collect::<Vec<_>>().join("/"));
   let mut mods = BTreeMap::new();
-  preprocess(&mut mods, &opts.include, src_root.as_ref(), &config.package.name, &config.package.registry)?;
+  preprocess(&mut mods, &opts.include, src_root.as_ref(), &config.package.name, &config.package.registry, &config.target_dir())?;
   let mods = mods.iter().map(|(i, v)| (i.show(), v)).collect::<BTreeMap<_,_>>();
   let mods_str = serde_json::to_string_pretty(&mods)?;
-  let _ = utils::compare_and_write(target_dir().join("mods.json"), mods_str.as_bytes())?;
+  let _ = utils::compare_and_write(config.target_dir().join("mods.json"), mods_str.as_bytes())?;
   Ok(())
 }
 
-pub fn src_files(target: &Target, units: &BTreeMap<String, Vec<Unit>>, is_target: bool) -> Result<String, anyhow::Error> {
+pub fn src_files(target: &Target, units: &BTreeMap<String, Vec<Unit>>, is_target: bool, config: &PackageConfig) -> Result<String, anyhow::Error> {
   let base = target.name.to_string();
   let features = target.features.keys().cloned().collect::<BTreeSet<_>>();
   let features_str = format!("{}{}{}", base, if is_target { "~target" } else { "" }, features.iter().map(|f| format!("-{}", f)).collect::<Vec<_>>().join(""));
   let paths_str = units.iter().filter(|(s, _)| !s.starts_with('@'))
     .flat_map(|(_, i)| i.iter()).filter(|i| i.features.is_empty() || !i.features.is_disjoint(&features))
     .chain(units.get(&format!("@{}.", base)).ok_or_else(|| anyhow::Error::msg("entrypoint not found"))?.iter())
-    .map(|i| if is_target { target_dir().join(&i.path).display().to_string() } else { i.path.display().to_string() }).collect::<Vec<_>>().join("\n");
-  let _ = utils::compare_and_write(target_dir().join("src_files").join(&features_str), paths_str.as_bytes())?;
+    .map(|i| if is_target { config.target_dir().join(&i.path).display().to_string() } else { i.path.display().to_string() }).collect::<Vec<_>>().join("\n");
+  let _ = utils::compare_and_write(config.target_dir().join("src_files").join(&features_str), paths_str.as_bytes())?;
   Ok(features_str)
 }
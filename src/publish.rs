@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use crate::{build, jar, toolchain};
+use crate::build::{TargetName, Profile};
+use crate::config::{PackageConfig, repo::RepoConfig, constant::*};
+use crate::utils;
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  /// name of a repository in repo config, or a local path
+  #[clap(long)]
+  pub repo: String,
+  /// defaults to `~/.carsier/repo.toml`
+  #[clap(long)]
+  pub repo_config: Option<PathBuf>,
+  /// show what would be written without writing, the lib target is still built
+  #[clap(long)]
+  pub dry_run: bool,
+}
+
+fn repo_root(opts: &Opts) -> Result<PathBuf, anyhow::Error> {
+  let config_path = opts.repo_config.clone().unwrap_or_else(repo_config);
+  let repo_config = if config_path.exists() { crate::load_repo_config(&config_path)? } else { RepoConfig::default() };
+  match repo_config.url(&opts.repo) {
+    Some(url) if url.starts_with("file://") => Ok(PathBuf::from(&url["file://".len()..])),
+    Some(url) => Err(anyhow::Error::msg(format!("only file:// repositories are supported, got {}", url))),
+    None => Ok(PathBuf::from(&opts.repo)),
+  }
+}
+
+pub fn group_id(config: &PackageConfig) -> String {
+  config.package.org.clone().unwrap_or_else(|| config.package.registry.clone())
+}
+
+pub fn artifact_id(config: &PackageConfig) -> String {
+  format!("{}_{}", config.package.name, crate::config::binary_version(&config.package.edition))
+}
+
+/// versions actually resolved by coursier, keyed by `org:artifact`
+fn resolved_versions(config: &PackageConfig) -> BTreeMap<String, String> {
+  let content = utils::load_content(config.target_dir().join("deps.out")).ok().flatten().unwrap_or_default();
+  content.lines().filter_map(|line| {
+    let mut parts = line.trim().split(':');
+    let (org, name, version) = (parts.next()?, parts.next()?, parts.next()?);
+    Some((format!("{}:{}", org, name), version.to_string()))
+  }).collect()
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// optional project information, skipped if not set
+fn pom_metadata(config: &PackageConfig) -> String {
+  let package = &config.package;
+  let mut result = String::new();
+  if let Some(description) = &package.description {
+    result += &format!("  <description>{}</description>\n", escape(description));
+  }
+  if let Some(homepage) = &package.homepage {
+    result += &format!("  <url>{}</url>\n", escape(homepage));
+  }
+  if let Some(license) = &package.license {
+    result += &format!("  <licenses>\n    <license>\n      <name>{}</name>\n    </license>\n  </licenses>\n", escape(license));
+  }
+  if !package.authors.is_empty() {
+    result += "  <developers>\n";
+    for author in &package.authors {
+      result += &format!("    <developer>\n      <name>{}</name>\n    </developer>\n", escape(author));
+    }
+    result += "  </developers>\n";
+  }
+  if let Some(repository) = &package.repository {
+    result += &format!("  <scm>\n    <url>{}</url>\n  </scm>\n", escape(repository));
+  }
+  // maven has no keywords element, so they go into a property
+  if !package.keywords.is_empty() {
+    result += &format!("  <properties>\n    <keywords>{}</keywords>\n  </properties>\n", escape(&package.keywords.join(",")));
+  }
+  result
+}
+
+/// `org.scala-lang` library the published classes are compiled against,
+/// at the version of the toolchain, or the resolved one of a local `scalac`
+fn scala_library(config: &PackageConfig, resolved: &BTreeMap<String, String>) -> Result<(&'static str, String), anyhow::Error> {
+  let edition = &config.package.edition;
+  let artifact = if crate::config::binary_version(edition) == "3" { "scala3-library_3" } else { "scala-library" };
+  let version = toolchain::load(config).ok().and_then(|t| t.version().map(|v| v.to_string()))
+    .or_else(|| resolved.get(&format!("org.scala-lang:{}", artifact)).cloned())
+    .or_else(|| if toolchain::is_full_version(edition) { Some(edition.to_string()) } else { None })
+    .ok_or_else(|| anyhow::Error::msg(format!("cannot find the version of {} {}, set a full edition like 2.13.12", artifact, edition)))?;
+  Ok((artifact, version))
+}
+
+pub fn pom(config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let resolved = resolved_versions(config);
+  let (library, library_version) = scala_library(config, &resolved)?;
+  let mut deps = format!(concat!(
+    "    <dependency>\n",
+    "      <groupId>org.scala-lang</groupId>\n",
+    "      <artifactId>{}</artifactId>\n",
+    "      <version>{}</version>\n",
+    "    </dependency>\n"), library, escape(&library_version));
+  for (name, dep) in &config.dependencies {
+    let dep = dep.as_dep();
+    let org = match &dep.org {
+      Some(org) => org,
+      None => {
+        warn!("crate dependency {} is not published into pom", name);
+        continue
+      }
+    };
+    let artifact = dep.artifact(name, &config.package.edition);
+    let version = resolved.get(&format!("{}:{}", org, artifact)).cloned()
+      .or_else(|| dep.version.example())
+      .ok_or_else(|| anyhow::Error::msg(format!("cannot find a version for {}", name)))?;
+    let mut exclusions = String::new();
+    for exclude in &dep.exclude {
+      let spec: crate::edit::Spec = exclude.parse()?;
+      let exclude_org = spec.org.clone().ok_or_else(|| anyhow::Error::msg(format!("invalid exclude {}", exclude)))?;
+      exclusions += &format!("        <exclusion>\n          <groupId>{}</groupId>\n          <artifactId>{}</artifactId>\n        </exclusion>\n",
+        escape(&exclude_org), escape(&spec.artifact(&config.package.edition)));
+    }
+    if !exclusions.is_empty() {
+      exclusions = format!("      <exclusions>\n{}      </exclusions>\n", exclusions);
+    }
+    deps += &format!(concat!(
+      "    <dependency>\n",
+      "      <groupId>{}</groupId>\n",
+      "      <artifactId>{}</artifactId>\n",
+      "      <version>{}</version>\n",
+      "{}{}",
+      "    </dependency>\n"), escape(org), escape(&artifact), escape(&version),
+      if dep.provided { "      <scope>provided</scope>\n" } else { "" }, exclusions);
+  }
+  Ok(format!(concat!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+    "<project xmlns=\"http://maven.apache.org/POM/4.0.0\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ",
+    "xsi:schemaLocation=\"http://maven.apache.org/POM/4.0.0 http://maven.apache.org/xsd/maven-4.0.0.xsd\">\n",
+    "  <modelVersion>4.0.0</modelVersion>\n",
+    "  <groupId>{}</groupId>\n",
+    "  <artifactId>{}</artifactId>\n",
+    "  <version>{}</version>\n",
+    "  <packaging>jar</packaging>\n",
+    "  <name>{}</name>\n",
+    "{}",
+    "  <dependencies>\n{}  </dependencies>\n",
+    "</project>\n"),
+    escape(&group_id(config)), escape(&artifact_id(config)), escape(&config.package.version), escape(&config.package.name), pom_metadata(config), deps))
+}
+
+fn sources_jar(config: &PackageConfig) -> Result<Vec<u8>, anyhow::Error> {
+  let mut jar = jar::JarBuilder::new(jar::Manifest::default());
+  let src_dir = config.target_dir().join("src");
+  for path in glob::glob(&src_dir.join("**").join("*.scala").to_string_lossy())?.filter_map(|i| i.ok()) {
+    let name = jar::entry_name(path.strip_prefix(&src_dir).unwrap_or(&path));
+    jar.add_file(&path, name)?;
+  }
+  jar.to_bytes()
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn write(path: &Path, content: &[u8], dry_run: bool) -> Result<(), anyhow::Error> {
+  use sha1::Digest;
+  let checksums = vec![
+    ("sha1", hex(&sha1::Sha1::digest(content))),
+    ("md5", hex(&md5::compute(content).0)),
+  ];
+  for (path, content) in Some((path.to_path_buf(), content.to_vec())).into_iter()
+    .chain(checksums.into_iter().map(|(ext, sum)| (utils::append_extension(path, ext), sum.into_bytes()))) {
+    if dry_run {
+      info!("would write {} ({} bytes)", path.display(), content.len());
+    } else {
+      let _ = utils::compare_and_write(&path, &content)?;
+      info!("wrote {}", path.display());
+    }
+  }
+  Ok(())
+}
+
+/// publish the lib target into a maven repository layout
+pub fn main(mut opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  if !config.package.publish {
+    return Err(anyhow::Error::msg(format!("{} is marked as `publish = false`", config.package.name)))
+  }
+  let root = repo_root(&opts)?;
+  opts.build.target.targets = vec![TargetName::Lib.to_string()];
+  let profile = Profile::from_release(opts.build.target.release);
+  let dry_run = opts.dry_run;
+  if dry_run {
+    info!("dry run, building {} but writing nothing into {}", config.package.name, root.display());
+  }
+  build::main(opts.build, config).context("build failed")?;
+  let (group, artifact, version) = (group_id(config), artifact_id(config), &config.package.version);
+  let dir = group.split('.').fold(root, |p, s| p.join(s)).join(&artifact).join(version);
+  let base = format!("{}-{}", artifact, version);
+  let jar_content = utils::load_content_raw(build::jar_path(&TargetName::Lib, profile, config))?.ok_or_else(|| anyhow::Error::msg("lib jar not found"))?;
+  let pom = pom(config)?;
+  write(&dir.join(format!("{}.jar", base)), &jar_content, dry_run)?;
+  write(&dir.join(format!("{}-sources.jar", base)), &sources_jar(config)?, dry_run)?;
+  write(&dir.join(format!("{}.pom", base)), pom.as_bytes(), dry_run)?;
+  info!("published {}:{}:{} => {}", group, artifact, version, dir.display());
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use clap::Clap;
+
+  fn config(dependencies: &str) -> PackageConfig {
+    let package = "[package]\nname = \"demo\"\nversion = \"0.2.0\"\norg = \"com.example\"\nedition = \"2.13.12\"\n";
+    toml::from_str(&format!("{}[dependencies]\n{}", package, dependencies)).unwrap()
+  }
+
+  #[test]
+  fn pom_dependencies() {
+    let pom = pom(&config(concat!(
+      "circe-core = { org = \"io.circe\", version = \"0.14.6\", exclude = [\"org.typelevel::cats-core\"] }\n",
+      "local = \"0.1\"\n",
+      "slf4j-api = { org = \"org.slf4j\", version = \"1.7.36\", java = true, provided = true }\n"))).unwrap();
+    assert!(pom.contains("  <groupId>com.example</groupId>\n  <artifactId>demo_2.13</artifactId>\n  <version>0.2.0</version>\n"), "{}", pom);
+    assert!(pom.contains(concat!(
+      "    <dependency>\n",
+      "      <groupId>org.scala-lang</groupId>\n",
+      "      <artifactId>scala-library</artifactId>\n",
+      "      <version>2.13.12</version>\n",
+      "    </dependency>\n")), "{}", pom);
+    assert!(pom.contains(concat!(
+      "      <groupId>io.circe</groupId>\n",
+      "      <artifactId>circe-core_2.13</artifactId>\n",
+      "      <version>0.14.6</version>\n",
+      "      <exclusions>\n",
+      "        <exclusion>\n",
+      "          <groupId>org.typelevel</groupId>\n",
+      "          <artifactId>cats-core_2.13</artifactId>\n")), "{}", pom);
+    assert!(pom.contains(concat!(
+      "      <groupId>org.slf4j</groupId>\n",
+      "      <artifactId>slf4j-api</artifactId>\n",
+      "      <version>1.7.36</version>\n",
+      "      <scope>provided</scope>\n")), "{}", pom);
+    assert_eq!(pom.matches("<scope>").count(), 1);
+    assert_eq!(pom.matches("<dependency>").count(), 3, "crate dependencies are not published");
+  }
+
+  #[test]
+  fn checksums_in_file_repo() {
+    let dir = utils::test_dir("publish");
+    let repo_config = dir.join("repo.toml");
+    std::fs::write(&repo_config, format!("[repos]\nlocal = {:?}\n", format!("file://{}", dir.join("repo").display()))).unwrap();
+    let opts = Opts::try_parse_from(vec!["publish".as_ref(), "--repo".as_ref(), "local".as_ref(), "--repo-config".as_ref(), repo_config.as_os_str()]).unwrap();
+    let root = repo_root(&opts).unwrap();
+    assert_eq!(root, dir.join("repo"));
+    let pom = root.join("demo_2.13-0.2.0.pom");
+    write(&pom, b"<project/>", true).unwrap();
+    assert!(!root.exists(), "a dry run writes nothing");
+    write(&pom, b"<project/>", false).unwrap();
+    assert_eq!(std::fs::read(&pom).unwrap(), b"<project/>");
+    assert_eq!(std::fs::read_to_string(utils::append_extension(&pom, "sha1")).unwrap(), "31a6e1717665b9fb4646a906d52abae65a7eefbc");
+    assert_eq!(std::fs::read_to_string(utils::append_extension(&pom, "md5")).unwrap(), "aedffc237dbf73096ec2ae39dba0f041");
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
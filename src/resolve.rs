@@ -1,39 +1,380 @@
-use crate::config::PackageConfig;
+use crate::config::{PackageConfig, Dependency, DependencyLike, binary_version};
 use crate::config::constant::*;
+use crate::edit::Spec;
 use crate::utils;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 
-#[derive(Clap)]
+/// crates being built by parent carsier processes for `[patch]`, so a patch cycle is an error
+const PATCH_STACK: &str = "CARSIER_PATCH_STACK";
+
+#[derive(Clap, Clone)]
 pub struct Opts {
   #[clap(long, default_value = "coursier")]
   pub coursier: String,
+  /// enabled features, they also select `[target.<feature-expr>]` dependencies
+  #[clap(long)]
+  pub features: Vec<String>,
+}
+
+/// which dependencies a classpath is resolved from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+  /// `[dependencies]`, what lib and bin targets compile against
+  Compile,
+  /// `[dependencies]` with `[dev-dependencies]`, for test and example targets
+  Dev,
+  /// `[dependencies]` without the subtrees only `provided` ones bring, packaged into dists and assemblies
+  Runtime,
+  /// `[build-dependencies]`, loaded by the compiler as plugins
+  Build,
+}
+impl Scope {
+  const ALL: [Scope; 4] = [Scope::Compile, Scope::Dev, Scope::Runtime, Scope::Build];
+
+  /// `target/<name>.in`, `.out` and `.classpath` hold its resolution
+  fn name(self) -> &'static str {
+    match self {
+      Scope::Compile => "deps",
+      Scope::Dev => "deps-dev",
+      Scope::Runtime => "deps-runtime",
+      Scope::Build => "deps-build",
+    }
+  }
+
+  pub fn file(self, ext: &str, config: &PackageConfig) -> PathBuf {
+    config.target_dir().join(format!("{}.{}", self.name(), ext))
+  }
+
+  /// with those of `[target]` sections whose feature expression holds for `features`
+  fn dependencies<'a>(self, config: &'a PackageConfig, features: &BTreeSet<String>) -> Vec<(&'a String, &'a DependencyLike)> {
+    let targets = config.target_dependencies(features);
+    let compile = config.dependencies.iter().chain(targets.iter().flat_map(|t| &t.dependencies));
+    match self {
+      Scope::Compile => compile.collect(),
+      Scope::Dev => compile.chain(&config.dev_dependencies).chain(targets.iter().flat_map(|t| &t.dev_dependencies)).collect(),
+      Scope::Runtime => compile.filter(|(_, dep)| !dep.as_dep().provided).collect(),
+      Scope::Build => config.build_dependencies.iter().collect(),
+    }
+  }
+}
+
+#[derive(Clap)]
+pub struct TreeOpts {
+  #[clap(flatten)]
+  pub resolve: Opts,
+}
+
+/// `(org, artifact)` of `org:name` for java or `org::name` for scala
+fn module(s: &str, edition: &str) -> Result<(String, String), anyhow::Error> {
+  let spec: Spec = s.parse()?;
+  match (&spec.org, &spec.version) {
+    (Some(org), None) => Ok((org.clone(), spec.artifact(edition))),
+    _ => Err(anyhow::Error::msg(format!("{} should be org:name or org::name", s))),
+  }
+}
+
+/// a crate of `[patch.<org>]` with its config
+struct Patched<'a> {
+  org: &'a str,
+  name: &'a str,
+  path: &'a Path,
+  config: PackageConfig,
+}
+
+fn load_patches(config: &PackageConfig) -> Result<Vec<Patched<'_>>, anyhow::Error> {
+  config.patch.iter().flat_map(|(org, patches)| patches.iter().map(move |(name, patch)| (org, name, patch))).map(|(org, name, patch)| {
+    let patched = crate::load_config(patch.path.join(toml_name())).with_context(|| format!("load patch {}", name))?;
+    Ok(Patched { org, name, path: &patch.path, config: patched })
+  }).collect()
+}
+
+/// the maven artifact a patch replaces, not suffixed if the patched dependency is a java one
+fn patched_artifact(config: &PackageConfig, org: &str, name: &str) -> String {
+  let edition = &config.package.edition;
+  let targets = config.target.values().flat_map(|t| t.dependencies.iter().chain(&t.dev_dependencies));
+  config.dependencies.iter().chain(&config.dev_dependencies).chain(targets)
+    .filter(|(n, _)| n.as_str() == name)
+    .map(|(_, dep)| dep.as_dep())
+    .find(|dep| dep.org.as_deref() == Some(org))
+    .map(|dep| dep.artifact(name, edition))
+    .unwrap_or_else(|| format!("{}_{}", name, binary_version(edition)))
+}
+
+/// maven dependencies of `scope` with the runtime ones of patched crates, so they are resolved together;
+/// patched artifacts are left out
+fn maven_dependencies(config: &PackageConfig, scope: Scope, features: &BTreeSet<String>, patches: &[Patched]) -> Result<Vec<(String, Dependency)>, anyhow::Error> {
+  let mut result = scope.dependencies(config, features).into_iter().map(|(name, dep)| (name.clone(), dep.as_dep().into_owned())).collect::<Vec<_>>();
+  for patch in patches.iter().filter(|_| scope != Scope::Build) {
+    let features = crate::build::cfg_features(&patch.config, &crate::build::target_features(&[], &patch.config)?);
+    let deps = Scope::Runtime.dependencies(&patch.config, &features).into_iter().map(|(name, dep)| (name.clone(), dep.as_dep().into_owned()));
+    // those the patched crate patches itself are not maven artifacts
+    result.extend(deps.filter(|(name, dep)| dep.org.as_ref().map(|org| patch.config.patched(org, name).is_none()).unwrap_or(false)));
+  }
+  result.retain(|(name, dep)| dep.org.as_ref().map(|org| config.patched(org, name).is_none()).unwrap_or(false));
+  Ok(result)
 }
 
-fn dump_deps_in(config: &PackageConfig) -> Result<String, anyhow::Error> {
+/// arguments of `coursier resolve` for `scope`, one per line; patched artifacts are excluded
+/// everywhere and noted in `# patch` lines, so changing a patch resolves again
+fn dump_deps_in(config: &PackageConfig, scope: Scope, features: &BTreeSet<String>, patches: &[Patched]) -> Result<String, anyhow::Error> {
   let mut result = String::new();
   let edition = &config.package.edition;
-  for (name, dep) in &config.dependencies {
-    let dep = dep.as_dep();
-    let dep = dep.as_ref();
-    if let Some(org) = &dep.org {
-      let name = if dep.java { name.to_string() } else { format!("{}_{}", name, edition) };
-      let version = dep.version.as_coursier().ok_or_else(|| anyhow::Error::msg("cannot find a version"))?;
-      result += &format!("{}:{}:{}\n", org, name, version);
+  for patch in patches.iter().filter(|_| scope != Scope::Build) {
+    result += &format!("# patch {}:{} => {}\n", patch.org, patch.name, patch.path.display());
+    result += &format!("--exclude\n{}:{}\n", patch.org, patched_artifact(config, patch.org, patch.name));
+  }
+  for (name, dep) in maven_dependencies(config, scope, features, patches)? {
+    let org = dep.org.as_ref().expect("maven dependencies have an org");
+    let version = dep.version.as_coursier().ok_or_else(|| anyhow::Error::msg("cannot find a version"))?;
+    result += &format!("{}:{}:{}", org, dep.artifact(&name, edition), version);
+    for exclude in &dep.exclude {
+      let (org, artifact) = module(exclude, edition).context("invalid exclude")?;
+      result += &format!(",exclude={}%{}", org, artifact);
     }
+    result += "\n";
+  }
+  for (key, version) in &config.overrides {
+    let (org, artifact) = module(key, edition).context("invalid [overrides]")?;
+    result += &format!("--force-version\n{}:{}:{}\n", org, artifact, version);
   }
   Ok(result)
 }
 
-pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
-  let coursier = &opts.coursier;
-  let deps_in = dump_deps_in(config)?;
-  let mut contd = utils::compare_and_write(target_dir().join("deps.in"), deps_in.as_bytes())?;
-  let deps_out = contd.exists_and_write(target_dir().join("deps.out"), || {
-    utils::call(coursier, vec!["resolve", "--quiet"].into_iter().chain(deps_in.lines())).map(|s| s.into())
-  })?;
+/// `org:name` of a `org:name:version:config` line of `coursier resolve`
+fn resolved_module(line: &str) -> &str {
+  let line = line.trim();
+  match line.match_indices(':').nth(1) {
+    Some((i, _)) => &line[..i],
+    None => line,
+  }
+}
+
+fn resolve_args(deps_in: &str) -> impl Iterator<Item = &str> {
+  deps_in.lines().filter(|l| !l.starts_with('#'))
+}
+
+/// build every patched crate and copy its lib jar to `target/patch/<artifact>-<version>.jar`,
+/// its dependencies are resolved with ours, see `maven_dependencies`
+fn patched_classpath(config: &PackageConfig, patches: &[Patched]) -> Result<Vec<PathBuf>, anyhow::Error> {
+  if patches.is_empty() {
+    return Ok(Vec::new())
+  }
+  let edition = &config.package.edition;
+  let exe = std::env::current_exe()?;
+  let mut building = std::env::var_os(PATCH_STACK).map(|s| std::env::split_paths(&s).collect::<Vec<_>>()).unwrap_or_default();
+  building.push(std::env::current_dir()?.canonicalize()?);
+  let mut result = Vec::new();
+  for patch in patches {
+    let path = patch.path.canonicalize().with_context(|| format!("patch {} not found at {}", patch.name, patch.path.display()))?;
+    if building.contains(&path) {
+      return Err(anyhow::Error::msg(format!("patch {} at {} is already being built, patches form a cycle", patch.name, patch.path.display())))
+    }
+    let mut args = vec!["-C".to_string(), patch.path.display().to_string()];
+    let target = if patch.config.package.editions.contains(edition) {
+      args.extend(vec!["--edition".to_string(), edition.clone()]);
+      patch.path.join(patch.config.select_edition(edition)?.target_dir())
+    } else if binary_version(&patch.config.package.edition) == binary_version(edition) {
+      patch.path.join(patch.config.target_dir())
+    } else {
+      return Err(anyhow::Error::msg(format!("patch {} is not built for edition {}", patch.name, edition)))
+    };
+    args.push("build".to_string());
+    let stack = std::env::join_paths(building.iter().chain(Some(&path)))?;
+    utils::call_command(std::process::Command::new(&exe).args(&args).env(PATCH_STACK, stack)).with_context(|| format!("build patch {}", patch.name))?;
+    // debug profile of the patched crate, see `build::jar_path`
+    let lib = target.join("build").join("lib.jar");
+    let jar = config.target_dir().join("patch").join(format!("{}-{}.jar", patched_artifact(config, patch.org, patch.name), patch.config.package.version));
+    let content = std::fs::read(&lib).with_context(|| format!("read {}", lib.display()))?;
+    let _ = utils::compare_and_write(&jar, &content)?;
+    info!("patch: {}:{} => {}", patch.org, patch.name, patch.path.display());
+    result.push(jar);
+  }
+  Ok(result)
+}
+
+/// resolve `scope` into its classpath file, compiler plugins are fetched without their dependencies
+fn resolve_scope(coursier: &str, scope: Scope, config: &PackageConfig, features: &BTreeSet<String>, patches: &[Patched], patched: &[PathBuf]) -> Result<(), anyhow::Error> {
+  let mut deps_in = dump_deps_in(config, scope, features, patches)?;
+  let compile_out = utils::load_content(Scope::Compile.file("out", config))?.unwrap_or_default();
+  if scope == Scope::Runtime {
+    // filtered from the compile resolution below, so resolved again when that changes
+    deps_in += &format!("# compile {:016x}\n", utils::hash_content(compile_out.as_bytes()));
+  }
+  let mut contd = utils::compare_and_write(scope.file("in", config), deps_in.as_bytes())?;
+  let deps_out = if maven_dependencies(config, scope, features, patches)?.is_empty() {
+    Vec::new()
+  } else if scope == Scope::Build {
+    deps_in.clone().into_bytes()
+  } else if scope == Scope::Runtime {
+    // versions are those compiled against, only modules no runtime dependency reaches are left out
+    contd.exists_and_write(scope.file("out", config), || {
+      let reached = utils::call(coursier, vec!["resolve", "--quiet"].into_iter().chain(resolve_args(&deps_in)))?;
+      let reached = reached.lines().map(resolved_module).collect::<BTreeSet<_>>();
+      Ok::<_, anyhow::Error>(compile_out.lines().filter(|l| reached.contains(resolved_module(l))).map(|l| format!("{}\n", l)).collect::<String>().into())
+    })?
+  } else {
+    contd.exists_and_write(scope.file("out", config), || {
+      utils::call(coursier, vec!["resolve", "--quiet"].into_iter().chain(resolve_args(&deps_in))).map(|s| s.into())
+    })?
+  };
   let deps_out = String::from_utf8(deps_out)?;
-  contd.exists_and_write(target_dir().join("deps.classpath"), || {
-    utils::call(coursier, vec!["fetch", "--quiet", "--classpath"].into_iter().chain(deps_out.lines()))
-      .map(|s| format!("{:?}", s.trim_end_matches('\n')).into())
+  let patched = if scope == Scope::Build { &[] } else { patched };
+  contd.exists_and_write(scope.file("classpath", config), || {
+    let cp = if deps_out.trim().is_empty() {
+      String::new()
+    } else {
+      // the output of resolve is already the whole resolution, so exclusions and overrides are kept
+      utils::call(coursier, vec!["fetch", "--quiet", "--intransitive", "--classpath"].into_iter().chain(deps_out.lines()))?
+    };
+    let cp = std::env::join_paths(std::env::split_paths(cp.trim_end_matches('\n')).filter(|p| !p.as_os_str().is_empty()).chain(patched.iter().cloned()))?;
+    Ok::<_, anyhow::Error>(format!("{:?}", cp.to_string_lossy()).into())
   })?;
   Ok(())
 }
+
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let patches = load_patches(config)?;
+  let patched = patched_classpath(config, &patches)?;
+  let features = crate::build::cfg_features(config, &crate::build::target_features(&opts.features, config)?);
+  for scope in Scope::ALL.iter() {
+    resolve_scope(&opts.coursier, *scope, config, &features, &patches, &patched).with_context(|| format!("resolve {}", scope.name()))?;
+  }
+  Ok(())
+}
+
+/// print the dependency tree with patches and overrides, patched crates are not built
+pub fn tree(opts: TreeOpts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let patches = load_patches(config)?;
+  let features = crate::build::cfg_features(config, &crate::build::target_features(&opts.resolve.features, config)?);
+  let deps_in = dump_deps_in(config, Scope::Compile, &features, &patches)?;
+  let tree = utils::call(&opts.resolve.coursier, vec!["resolve", "--quiet", "--tree"].into_iter().chain(resolve_args(&deps_in)))?;
+  print!("{}", tree);
+  for line in deps_in.lines().filter_map(|l| l.strip_prefix("# patch ")) {
+    println!("{} (patched)", line);
+  }
+  for (key, version) in &config.overrides {
+    println!("{} => {} (overridden)", key, version);
+  }
+  Ok(())
+}
+
+/// jars resolved into `target/deps.classpath`
+pub fn classpath(config: &PackageConfig) -> Result<Vec<PathBuf>, anyhow::Error> {
+  scope_classpath(Scope::Compile, config)
+}
+
+/// jars resolved into `target/<scope>.classpath`
+pub fn scope_classpath(scope: Scope, config: &PackageConfig) -> Result<Vec<PathBuf>, anyhow::Error> {
+  Ok(utils::read_args_file(scope.file("classpath", config))?.iter().flat_map(std::env::split_paths).filter(|p| !p.as_os_str().is_empty()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(s: &str) -> PackageConfig {
+    toml::from_str(s).unwrap()
+  }
+
+  const PACKAGE: &str = r#"
+[package]
+name = "app"
+version = "0.1.0"
+edition = "2.13"
+"#;
+
+  #[test]
+  fn excludes_and_overrides() {
+    let config = config(&format!("{}{}", PACKAGE, r#"
+[dependencies]
+cats-core = { version = "2.9.0", org = "org.typelevel", exclude = ["org.scala-lang:scala-reflect", "org.typelevel::cats-kernel"] }
+guava = { version = "=31.1", org = "com.google.guava", java = true }
+
+[overrides]
+"org.typelevel::cats-kernel" = "2.8.0"
+"com.google.guava:failureaccess" = "1.0.1"
+"#));
+    let deps_in = dump_deps_in(&config, Scope::Compile, &BTreeSet::new(), &[]).unwrap();
+    assert_eq!(deps_in, "\
+org.typelevel:cats-core_2.13:2.9.0,exclude=org.scala-lang%scala-reflect,exclude=org.typelevel%cats-kernel_2.13
+com.google.guava:guava:31.1
+--force-version
+com.google.guava:failureaccess:1.0.1
+--force-version
+org.typelevel:cats-kernel_2.13:2.8.0
+");
+  }
+
+  #[test]
+  fn dev_dependencies_only_in_dev() {
+    let config = config(&format!("{}{}", PACKAGE, r#"
+[dependencies]
+cats-core = { version = "2.9.0", org = "org.typelevel" }
+
+[dev-dependencies]
+munit = { version = "0.7.29", org = "org.scalameta" }
+"#));
+    let names = |scope: Scope| scope.dependencies(&config, &BTreeSet::new()).into_iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names(Scope::Compile), vec!["cats-core"]);
+    assert_eq!(names(Scope::Dev), vec!["cats-core", "munit"]);
+    assert!(dump_deps_in(&config, Scope::Dev, &BTreeSet::new(), &[]).unwrap().contains("org.scalameta:munit_2.13:0.7.29"));
+    assert!(!dump_deps_in(&config, Scope::Compile, &BTreeSet::new(), &[]).unwrap().contains("munit"));
+  }
+
+  #[test]
+  fn invalid_exclude() {
+    let config = config(&format!("{}{}", PACKAGE, r#"
+[dependencies]
+cats-core = { version = "2.9.0", org = "org.typelevel", exclude = ["cats-kernel"] }
+"#));
+    assert!(dump_deps_in(&config, Scope::Compile, &BTreeSet::new(), &[]).is_err());
+  }
+
+  #[test]
+  fn patches_are_excluded_and_their_dependencies_resolved() {
+    let config = config(&format!("{}{}", PACKAGE, r#"
+[dependencies]
+core = { version = "1.0.0", org = "org.lib" }
+jcore = { version = "1.0.0", org = "org.lib", java = true }
+
+[patch."org.lib"]
+core = { path = "../core" }
+jcore = { path = "../jcore" }
+"#));
+    let core = self::config(r#"
+[package]
+name = "core"
+version = "1.1.0"
+edition = "2.13"
+
+[dependencies]
+shapeless = { version = "2.3.10", org = "com.chuusai" }
+servlet = { version = "4.0.1", org = "javax.servlet", java = true, provided = true }
+"#);
+    let jcore = self::config(r#"
+[package]
+name = "jcore"
+version = "1.1.0"
+edition = "2.13"
+
+[dependencies]
+"#);
+    let patches = vec![
+      Patched { org: "org.lib", name: "core", path: Path::new("../core"), config: core },
+      Patched { org: "org.lib", name: "jcore", path: Path::new("../jcore"), config: jcore },
+    ];
+    let deps_in = dump_deps_in(&config, Scope::Compile, &BTreeSet::new(), &patches).unwrap();
+    assert_eq!(deps_in, "\
+# patch org.lib:core => ../core
+--exclude
+org.lib:core_2.13
+# patch org.lib:jcore => ../jcore
+--exclude
+org.lib:jcore
+com.chuusai:shapeless_2.13:2.3.10
+");
+    assert_eq!(dump_deps_in(&config, Scope::Build, &BTreeSet::new(), &patches).unwrap(), "");
+  }
+}
@@ -0,0 +1,240 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Context;
+use crate::{toolchain, utils};
+use crate::config::PackageConfig;
+
+/// the compiler reported errors, with everything it printed
+#[derive(Debug, thiserror::Error)]
+#[error("compile failed: {0}")]
+pub struct CompileError(pub String);
+
+/// the server did not answer in time, it is stopped and the compile runs without it
+#[derive(Debug, thiserror::Error)]
+#[error("compile server did not answer in {0:?}")]
+pub struct Timeout(pub Duration);
+
+/// for connecting and for requests other than compile
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const COMPILE_TIMEOUT: Duration = Duration::from_secs(600);
+
+const SERVER_CODE: &str = include_str!("../configs/server.scala");
+const SERVER_MAIN: &str = "carsier.server.Main";
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(subcommand)]
+  pub cmd: Command,
+}
+
+#[derive(Clap)]
+pub enum Command {
+  /// start the compile server if not running
+  Start,
+  /// stop the running compile server
+  Stop,
+  /// show whether the compile server is running
+  Status,
+}
+
+fn server_dir(config: &PackageConfig) -> PathBuf {
+  config.target_dir().join("server")
+}
+
+fn port_file(config: &PackageConfig) -> PathBuf {
+  server_dir(config).join("port")
+}
+
+fn port(config: &PackageConfig) -> Option<u16> {
+  utils::load_content(port_file(config)).ok()??.trim().parse().ok()
+}
+
+/// count of strings, then each string as its length and utf-8 bytes, all lengths big endian i32
+fn encode_request(cmd: &str, args: &[String]) -> Vec<u8> {
+  let mut content = Vec::new();
+  content.extend_from_slice(&(args.len() as i32 + 1).to_be_bytes());
+  for s in Some(cmd).into_iter().chain(args.iter().map(String::as_str)) {
+    content.extend_from_slice(&(s.len() as i32).to_be_bytes());
+    content.extend_from_slice(s.as_bytes());
+  }
+  content
+}
+
+/// status byte, 0 for ok, then the length and utf-8 bytes of the output
+fn decode_response(response: &[u8]) -> Result<(bool, String), anyhow::Error> {
+  let invalid = || anyhow::Error::msg(format!("unexpected response from compile server: {}", String::from_utf8_lossy(response)));
+  if response.len() < 5 {
+    return Err(invalid())
+  }
+  let len = i32::from_be_bytes([response[1], response[2], response[3], response[4]]) as usize;
+  let output = response.get(5..5 + len).filter(|_| response.len() == 5 + len).ok_or_else(invalid)?;
+  Ok((response[0] == 0, String::from_utf8_lossy(output).to_string()))
+}
+
+/// send a request to the worker, returns whether it succeeded and what it printed,
+/// fails with `Timeout` if the worker takes longer than `timeout`
+fn request(port: u16, cmd: &str, args: &[String], timeout: Duration) -> Result<(bool, String), anyhow::Error> {
+  let timed_out = |e: std::io::Error| -> anyhow::Error {
+    match e.kind() {
+      std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => Timeout(timeout).into(),
+      _ => e.into(),
+    }
+  };
+  let mut stream = TcpStream::connect_timeout(&SocketAddr::from(([127, 0, 0, 1], port)), PING_TIMEOUT)?;
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+  stream.write_all(&encode_request(cmd, args)).map_err(timed_out)?;
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).map_err(timed_out)?;
+  decode_response(&response)
+}
+
+/// port of the running server, `None` if it is not running or not responding
+pub fn status(config: &PackageConfig) -> Option<u16> {
+  let port = port(config)?;
+  match request(port, "ping", &[], PING_TIMEOUT) {
+    Ok((true, _)) => Some(port),
+    _ => None,
+  }
+}
+
+/// the toolchain the running worker was started with
+fn toolchain_stamp(config: &PackageConfig) -> PathBuf {
+  server_dir(config).join("toolchain.json")
+}
+
+/// compile the worker with the compiler it will run, recompiled when either changes
+fn ensure_worker(toolchain: &toolchain::Toolchain, stamp: &str, config: &PackageConfig) -> Result<PathBuf, anyhow::Error> {
+  let classes = server_dir(config).join("classes");
+  let src = server_dir(config).join("Server.scala");
+  let code_changed = utils::compare_and_write(&src, SERVER_CODE.as_bytes())?;
+  let toolchain_changed = utils::compare_and_write(toolchain_stamp(config), stamp.as_bytes())?;
+  if let (utils::FileDep::Unchanged, utils::FileDep::Unchanged, true) = (code_changed, toolchain_changed, classes.exists()) {
+    return Ok(classes)
+  }
+  let _ = std::fs::remove_dir_all(&classes);
+  std::fs::create_dir_all(&classes)?;
+  toolchain.call(vec!["-d".as_ref(), classes.as_os_str(), src.as_os_str()])?;
+  info!("compiled compile server => {}", classes.display());
+  Ok(classes)
+}
+
+/// start the worker on the compiler of `target/toolchain.json`,
+/// a worker running another compiler is restarted
+pub fn start(config: &PackageConfig) -> Result<u16, anyhow::Error> {
+  let toolchain = toolchain::load(config).context("compile server needs a prepared toolchain, run `carsier build` first")?;
+  let jars = toolchain.compiler_jars().ok_or_else(|| anyhow::Error::msg("compile server needs a resolved compiler, not scalac of [toolchain]"))?;
+  let stamp = serde_json::to_string_pretty(&toolchain)?;
+  if let Some(port) = status(config) {
+    if utils::load_content(toolchain_stamp(config))?.as_deref() == Some(stamp.as_str()) {
+      return Ok(port)
+    }
+    info!("compile server toolchain changed, restarting");
+    request(port, "stop", &[], PING_TIMEOUT)?;
+  }
+  let classes = ensure_worker(&toolchain, &stamp, config).context("compile server worker")?;
+  let cp = std::env::join_paths(jars.iter().cloned().chain(Some(classes)))?;
+  let _ = std::fs::remove_file(port_file(config));
+  let log = std::fs::File::create(server_dir(config).join("output.log"))?;
+  let child = toolchain.command()
+    .args(["-cp".as_ref(), cp.as_os_str(), SERVER_MAIN.as_ref(), port_file(config).as_os_str()])
+    .stdin(std::process::Stdio::null())
+    .stdout(log.try_clone()?)
+    .stderr(log)
+    .spawn()?;
+  let _ = utils::compare_and_write(server_dir(config).join("pid"), child.id().to_string().as_bytes())?;
+  for _ in 0..300 {
+    std::thread::sleep(Duration::from_millis(100));
+    if let Some(port) = status(config) {
+      info!("compile server started on port {}", port);
+      return Ok(port)
+    }
+  }
+  Err(anyhow::Error::msg(format!("compile server not responding, see {}", server_dir(config).join("output.log").display())))
+}
+
+pub fn stop(config: &PackageConfig) -> Result<(), anyhow::Error> {
+  if let Some(port) = status(config) {
+    request(port, "stop", &[], PING_TIMEOUT)?;
+    info!("compile server stopped");
+  } else {
+    info!("compile server not running");
+  }
+  let _ = std::fs::remove_file(port_file(config));
+  let _ = std::fs::remove_file(server_dir(config).join("pid"));
+  Ok(())
+}
+
+/// kill a worker which stopped answering, by the pid it was started with
+pub fn kill(config: &PackageConfig) {
+  let pid_file = server_dir(config).join("pid");
+  if let Ok(Some(pid)) = utils::load_content(&pid_file) {
+    let mut command = if cfg!(windows) {
+      let mut command = std::process::Command::new("taskkill");
+      command.args(["/F", "/PID", pid.trim()]);
+      command
+    } else {
+      let mut command = std::process::Command::new("kill");
+      command.args(["-9", pid.trim()]);
+      command
+    };
+    if let Err(e) = command.status() {
+      warn!("failed to kill compile server {}: {}", pid.trim(), e);
+    }
+  }
+  let _ = std::fs::remove_file(port_file(config));
+  let _ = std::fs::remove_file(pid_file);
+}
+
+/// compile with the server, start it first if needed, the server writes
+/// the classes each source defines and uses into `analysis`, see `incremental::parse`
+pub fn compile(analysis: &Path, args: &[String], config: &PackageConfig) -> Result<String, anyhow::Error> {
+  let port = start(config)?;
+  let args = Some(analysis.display().to_string()).into_iter().chain(args.iter().cloned()).collect::<Vec<_>>();
+  match request(port, "compile", &args, COMPILE_TIMEOUT)? {
+    (true, output) => Ok(output),
+    (false, output) => Err(CompileError(output).into()),
+  }
+}
+
+pub fn main(opts: Opts, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  match opts.cmd {
+    Command::Start => { start(config)?; },
+    Command::Stop => stop(config)?,
+    Command::Status => match status(config) {
+      Some(port) => info!("compile server running on port {}", port),
+      None => info!("compile server not running"),
+    },
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn empty_and_multiline_args() {
+    let args = vec!["-d".to_string(), String::new(), "a\n\nb".to_string()];
+    let request = encode_request("compile", &args);
+    let mut expected = 4i32.to_be_bytes().to_vec();
+    for s in &["compile", "-d", "", "a\n\nb"] {
+      expected.extend_from_slice(&(s.len() as i32).to_be_bytes());
+      expected.extend_from_slice(s.as_bytes());
+    }
+    assert_eq!(request, expected);
+  }
+
+  #[test]
+  fn responses() {
+    let mut response = vec![1];
+    response.extend_from_slice(&6i32.to_be_bytes());
+    response.extend_from_slice(b"err\n\n\0");
+    assert_eq!(decode_response(&response).unwrap(), (false, "err\n\n\0".to_string()));
+    assert_eq!(decode_response(&[0, 0, 0, 0, 0]).unwrap(), (true, String::new()));
+    assert!(decode_response(&[0, 0, 0, 0, 9, b'x']).is_err());
+    assert!(decode_response(b"ok").is_err());
+  }
+}
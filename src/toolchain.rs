@@ -0,0 +1,277 @@
+use std::path::PathBuf;
+use anyhow::Context;
+use crate::config::{PackageConfig, binary_version, constant::*};
+use crate::utils;
+
+/// how sources are compiled, written to `target/toolchain.json` before compiling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Toolchain {
+  pub compiler: Compiler,
+  /// java of the selected jdk, runs resolved compilers
+  pub java: PathBuf,
+  /// passed to every compile, e.g. `-release 17`
+  pub options: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compiler {
+  /// `scalac` of `[toolchain]`, used as-is
+  Local { scalac: PathBuf },
+  /// compiler jars resolved by coursier, run with `java -cp`
+  Resolved { version: String, main: String, jars: Vec<PathBuf> },
+}
+
+impl Toolchain {
+  /// scala library jars the compiled code needs on its classpath
+  pub fn library(&self) -> Vec<PathBuf> {
+    match &self.compiler {
+      Compiler::Local { .. } => Vec::new(),
+      Compiler::Resolved { jars, .. } => library_jars(jars),
+    }
+  }
+
+  /// exact scala version of a resolved compiler, its library has the same version
+  pub fn version(&self) -> Option<&str> {
+    match &self.compiler {
+      Compiler::Local { .. } => None,
+      Compiler::Resolved { version, .. } => Some(version),
+    }
+  }
+
+  /// jars of a resolved compiler, which could be run in a compile server
+  pub fn compiler_jars(&self) -> Option<&[PathBuf]> {
+    match &self.compiler {
+      Compiler::Local { .. } => None,
+      Compiler::Resolved { jars, .. } => Some(jars),
+    }
+  }
+
+  /// `program` on the selected jdk, `JAVA_HOME` and `PATH` of the child point to it
+  pub fn jdk_command<S: AsRef<std::ffi::OsStr>>(&self, program: S) -> std::process::Command {
+    let mut command = std::process::Command::new(program);
+    if let Some(bin) = self.java.parent().filter(|bin| !bin.as_os_str().is_empty()) {
+      let path = std::env::var_os("PATH").map(|p| std::env::split_paths(&p).collect::<Vec<_>>()).unwrap_or_default();
+      if let Ok(path) = std::env::join_paths(Some(bin.to_path_buf()).into_iter().chain(path)) {
+        command.env("PATH", path);
+      }
+      if let Some(home) = bin.parent() {
+        command.env("JAVA_HOME", home);
+      }
+    }
+    command
+  }
+
+  /// `java` of the selected jdk
+  pub fn command(&self) -> std::process::Command {
+    self.jdk_command(&self.java)
+  }
+
+  /// a tool shipped with the compiler: `script` next to a local scalac, like `scaladoc`,
+  /// or class `main` in the resolved compiler jars, like `scala.tools.nsc.ScalaDoc`,
+  /// `java_options` go to the jvm running it, through `-J` of a script
+  pub fn tool(&self, script: &str, main: &str, java_options: &[String]) -> Result<std::process::Command, anyhow::Error> {
+    match &self.compiler {
+      Compiler::Local { scalac } => {
+        let mut command = self.jdk_command(scalac.with_file_name(script));
+        command.args(java_options.iter().map(|o| format!("-J{}", o)));
+        Ok(command)
+      },
+      Compiler::Resolved { jars, .. } => {
+        let mut command = self.command();
+        command.args(java_options).arg("-cp").arg(std::env::join_paths(jars)?).arg(main);
+        Ok(command)
+      },
+    }
+  }
+
+  /// run the compiler with `options` followed by `args`
+  pub fn call<Args, S>(&self, args: Args) -> Result<String, anyhow::Error>
+    where Args: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr> {
+    utils::call_command(&mut self.scalac_command(args)?)
+  }
+
+  /// the compiler with `options` and `args`, not started yet
+  pub fn scalac_command<Args, S>(&self, args: Args) -> Result<std::process::Command, anyhow::Error>
+    where Args: IntoIterator<Item = S>, S: AsRef<std::ffi::OsStr> {
+    let args = self.options.iter().map(std::ffi::OsString::from).chain(args.into_iter().map(|a| a.as_ref().to_owned()));
+    let mut command = match &self.compiler {
+      Compiler::Local { scalac } => self.jdk_command(scalac),
+      Compiler::Resolved { main, .. } => self.tool("scalac", main, &[])?,
+    };
+    command.args(args);
+    Ok(command)
+  }
+}
+
+/// a jdk found by `find_jdk`
+#[derive(Debug, Clone)]
+pub struct Jdk {
+  pub java: PathBuf,
+  /// feature version, `8` for `1.8.0_292`
+  pub version: u32,
+}
+
+/// `java-home` of `[package.jvm]`, or `JAVA_HOME`
+pub fn java_home(config: &PackageConfig) -> Option<PathBuf> {
+  config.package.jvm.java_home.clone().or_else(|| std::env::var_os("JAVA_HOME").map(PathBuf::from))
+}
+
+/// `openjdk version "17.0.2"` => 17, `java version "1.8.0_292"` => 8
+fn parse_java_version(output: &str) -> Option<u32> {
+  let version = output.lines().find_map(|l| l.split('"').nth(1))?;
+  let mut numbers = version.split(|c: char| !c.is_ascii_digit());
+  match numbers.next()?.parse().ok()? {
+    1 => numbers.next()?.parse().ok(),
+    n => Some(n),
+  }
+}
+
+/// the jdk of `java_home`, or `java` on PATH, failing if it can't target `release` of `[package.jvm]`
+pub fn find_jdk(config: &PackageConfig) -> Result<Jdk, anyhow::Error> {
+  let java = match java_home(config) {
+    Some(home) => home.join("bin").join("java"),
+    None => PathBuf::from("java"),
+  };
+  // `java -version` prints to stderr
+  let output = std::process::Command::new(&java).arg("-version").stdin(std::process::Stdio::null()).output()
+    .with_context(|| format!("run {}, set java-home in [package.jvm] or JAVA_HOME", java.display()))?;
+  let output = String::from_utf8_lossy(&output.stderr).to_string() + &String::from_utf8_lossy(&output.stdout);
+  let version = parse_java_version(&output).ok_or_else(|| anyhow::Error::msg(format!("unknown version of {}: {}", java.display(), output.trim())))?;
+  let required = config.package.jvm.release.unwrap_or(MIN_JAVA_VERSION).max(MIN_JAVA_VERSION);
+  if version < required {
+    return Err(anyhow::Error::msg(format!("jdk {} at {} is older than {} required, set java-home in [package.jvm] or JAVA_HOME", version, java.display(), required)))
+  }
+  Ok(Jdk { java, version })
+}
+
+/// oldest jdk scala 2.12 and later run on
+const MIN_JAVA_VERSION: u32 = 8;
+
+fn toolchain_file(config: &PackageConfig) -> PathBuf {
+  config.target_dir().join("toolchain.json")
+}
+
+/// `scala3-compiler_3` for scala 3 editions, `scala-compiler` otherwise
+pub fn compiler_artifact(edition: &str) -> &'static str {
+  if binary_version(edition) == "3" { "scala3-compiler_3" } else { "scala-compiler" }
+}
+
+fn compiler_main(edition: &str) -> &'static str {
+  if binary_version(edition) == "3" { "dotty.tools.dotc.Main" } else { "scala.tools.nsc.Main" }
+}
+
+/// main class of the repl in the compiler jars
+pub fn repl_main(edition: &str) -> &'static str {
+  if binary_version(edition) == "3" { "dotty.tools.repl.Main" } else { "scala.tools.nsc.MainGenericRunner" }
+}
+
+/// scala library jars among the compiler jars of `resolve`
+pub fn library_jars(jars: &[PathBuf]) -> Vec<PathBuf> {
+  jars.iter().filter(|jar| {
+    let name = jar.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    name.starts_with("scala-library-") || name.starts_with("scala3-library_3-")
+  }).cloned().collect()
+}
+
+/// `2.13.12` is used as-is, `2.13` means its latest patch release
+pub fn is_full_version(edition: &str) -> bool {
+  edition.split('.').count() >= 3
+}
+
+/// exact compiler version and jars for the edition, jars of a version are cached
+/// in `~/.carsier/toolchains` and the latest patch of an edition in `target/toolchain.*`
+pub fn resolve(coursier: &str, config: &PackageConfig) -> Result<(String, Vec<PathBuf>), anyhow::Error> {
+  let edition = &config.package.edition;
+  let artifact = compiler_artifact(edition);
+  let version = if is_full_version(edition) {
+    edition.to_string()
+  } else {
+    let spec = format!("org.scala-lang:{}:{}+", artifact, edition);
+    let mut contd = utils::compare_and_write(config.target_dir().join("toolchain.in"), spec.as_bytes())?;
+    let resolved = contd.exists_and_write(config.target_dir().join("toolchain.out"), || {
+      utils::call(coursier, ["resolve", "--quiet", spec.as_str()]).map(|s| s.into())
+    })?;
+    String::from_utf8(resolved)?.lines().filter_map(|line| {
+      let mut parts = line.trim().split(':');
+      match (parts.next(), parts.next(), parts.next()) {
+        (Some("org.scala-lang"), Some(name), Some(version)) if name == artifact => Some(version.to_string()),
+        _ => None,
+      }
+    }).next().ok_or_else(|| anyhow::Error::msg(format!("scala compiler {} not found", edition)))?
+  };
+  let cache = home_dir().join("toolchains").join(format!("{}-{}.classpath", artifact, version));
+  if !cache.exists() {
+    let cp = utils::call(coursier, ["fetch", "--quiet", "--classpath", &format!("org.scala-lang:{}:{}", artifact, version)])?;
+    let _ = utils::compare_and_write(&cache, format!("{:?}", cp.trim_end_matches('\n')).as_bytes())?;
+    info!("toolchain: {} {} => {}", artifact, version, cache.display());
+  }
+  let jars = utils::read_args_file(&cache)?.iter().flat_map(std::env::split_paths).collect();
+  Ok((version, jars))
+}
+
+/// `-release` of `[package.jvm]`, passed to every compile
+pub fn release_options(config: &PackageConfig) -> Vec<String> {
+  match config.package.jvm.release {
+    Some(release) => vec!["-release".to_string(), release.to_string()],
+    None => Vec::new(),
+  }
+}
+
+/// pick the jdk and the compiler of the edition, or `[toolchain]` if set
+pub fn prepare(coursier: &str, config: &PackageConfig) -> Result<(), anyhow::Error> {
+  let jdk = find_jdk(config)?;
+  debug!("jdk {} => {}", jdk.version, jdk.java.display());
+  let compiler = match &config.toolchain.scalac {
+    Some(scalac) => Compiler::Local { scalac: scalac.clone() },
+    None => {
+      let edition = &config.package.edition;
+      let (version, jars) = resolve(coursier, config).context("resolve scala compiler")?;
+      Compiler::Resolved { version, main: compiler_main(edition).to_string(), jars }
+    },
+  };
+  let toolchain = Toolchain { compiler, java: jdk.java, options: release_options(config) };
+  let _ = utils::compare_and_write(toolchain_file(config), serde_json::to_string_pretty(&toolchain)?.as_bytes())?;
+  Ok(())
+}
+
+/// the toolchain written by `prepare`
+pub fn load(config: &PackageConfig) -> Result<Toolchain, anyhow::Error> {
+  let content = utils::load_content(toolchain_file(config))?.ok_or_else(|| anyhow::Error::msg("toolchain not prepared"))?;
+  Ok(serde_json::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn java_version() {
+    let openjdk = "openjdk version \"17.0.2\" 2022-01-18\nOpenJDK Runtime Environment (build 17.0.2+8-86)\n";
+    assert_eq!(parse_java_version(openjdk), Some(17));
+    assert_eq!(parse_java_version("java version \"1.8.0_292\"\nJava(TM) SE Runtime Environment"), Some(8));
+    assert_eq!(parse_java_version("openjdk version \"21\" 2023-09-19"), Some(21));
+    assert_eq!(parse_java_version("openjdk version \"11.0.20.1\" 2023-08-24"), Some(11));
+    assert_eq!(parse_java_version("Picked up JAVA_TOOL_OPTIONS: -Xmx1g\nopenjdk version \"17.0.8\""), Some(17));
+  }
+
+  #[test]
+  fn unknown_java_version() {
+    assert_eq!(parse_java_version(""), None);
+    assert_eq!(parse_java_version("java: command not found"), None);
+    assert_eq!(parse_java_version("java version \"1\""), None);
+  }
+
+  #[test]
+  fn tool_java_options() {
+    let args = |compiler: Compiler| {
+      let toolchain = Toolchain { compiler, java: PathBuf::from("java"), options: Vec::new() };
+      let command = toolchain.tool("scala", "scala.tools.nsc.MainGenericRunner", &["-Xmx2g".to_string(), "-Da=b".to_string()]).unwrap();
+      command.get_args().map(|a| a.to_string_lossy().to_string()).collect::<Vec<_>>()
+    };
+    let resolved = Compiler::Resolved { version: "2.13.12".to_string(), main: "scala.tools.nsc.Main".to_string(), jars: vec![PathBuf::from("c.jar")] };
+    assert_eq!(args(resolved), vec!["-Xmx2g", "-Da=b", "-cp", "c.jar", "scala.tools.nsc.MainGenericRunner"]);
+    assert_eq!(args(Compiler::Local { scalac: PathBuf::from("/opt/scala/bin/scalac") }), vec!["-J-Xmx2g", "-J-Da=b"]);
+  }
+}
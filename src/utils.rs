@@ -1,5 +1,6 @@
 use std::path::{Path, PathBuf};
 use thiserror::Error;
+use anyhow::Context;
 
 pub trait ResultLog<T>: Sized {
   fn ok_or_log(self, lvl: log::Level) -> Option<T>;
@@ -30,26 +31,60 @@ impl CallError {
   fn new<S: AsRef<str>>(s: S, i: std::process::Output) -> Self {
     Self(s.as_ref().to_string(), i.status.code().unwrap_or(-1), i)
   }
-  fn output(&self) -> String {
+  /// stdout then stderr of the process
+  pub fn output(&self) -> String {
     format!("{}{}", String::from_utf8_lossy(&self.2.stdout), String::from_utf8_lossy(&self.2.stderr))
   }
 }
 
 pub fn call<Args, S1>(cmd: &str, args: Args) -> Result<String, anyhow::Error>
   where Args: IntoIterator<Item = S1>, S1: AsRef<std::ffi::OsStr> {
-  use std::process::*;
+  call_command(std::process::Command::new(cmd).args(args))
+}
+
+/// `call` with a prepared command, for one needing its own environment
+pub fn call_command(command: &mut std::process::Command) -> Result<String, anyhow::Error> {
   // TODO: encoding
-  let args = args.into_iter().collect::<Vec<_>>();
-  debug!("call: {} {:?}", cmd, args.iter().map(|i| i.as_ref()).collect::<Vec<_>>());
-  let p = Command::new(cmd).stdin(Stdio::null()).args(args).output()?;
+  debug!("call: {:?}", command);
+  let p = command.stdin(std::process::Stdio::null()).output()?;
 
   if p.status.success() {
     Ok(String::from_utf8(p.stdout)?)
   } else {
-    Err(CallError::new(cmd, p).into())
+    Err(CallError::new(command.get_program().to_string_lossy(), p).into())
+  }
+}
+
+/// `call_command` keeping stderr as well, where compilers report warnings
+pub fn call_output(command: &mut std::process::Command) -> Result<String, anyhow::Error> {
+  debug!("call: {:?}", command);
+  let p = command.stdin(std::process::Stdio::null()).output()?;
+  if p.status.success() {
+    Ok(format!("{}{}", String::from_utf8_lossy(&p.stdout), String::from_utf8_lossy(&p.stderr)))
+  } else {
+    Err(CallError::new(command.get_program().to_string_lossy(), p).into())
+  }
+}
+
+/// run a command attached to the current terminal
+pub fn exec(command: &mut std::process::Command) -> Result<(), anyhow::Error> {
+  debug!("exec: {:?}", command);
+  let status = command.status()?;
+  if status.success() {
+    Ok(())
+  } else {
+    Err(anyhow::Error::msg(format!("process {} exited with {}", command.get_program().to_string_lossy(), status)))
   }
 }
 
+/// `a.jar` => `a.jar.sha1`
+pub fn append_extension(path: &Path, ext: &str) -> PathBuf {
+  let mut filename = path.file_name().unwrap_or_default().to_os_string();
+  filename.push(".");
+  filename.push(ext);
+  path.with_file_name(filename)
+}
+
 trait PathExt {
   fn lock(&self) -> Option<PathBuf>;
 }
@@ -125,8 +160,57 @@ pub fn compare_and_write<P: AsRef<Path>>(path: P, content: &[u8]) -> Result<File
     }
   }
   let lock_filename = path.as_ref().lock().ok_or_else(|| anyhow::Error::msg("root path"))?;
-  let mut f = OpenOptions::new().write(true).create_new(true).open(&lock_filename)?;
+  let mut f = OpenOptions::new().write(true).create_new(true).open(&lock_filename)
+    .with_context(|| format!("create {}, remove stale lock files with `carsier clean`", lock_filename.display()))?;
   f.write_all(content)?;
   std::fs::rename(&lock_filename, path)?;
   Ok(FileDep::Touched)
 }
+
+/// `target/carsier.flock`, every running carsier holds a shared lock on it
+/// and `carsier clean` an exclusive one, so it never removes files in use
+pub fn target_lock() -> Result<std::fs::File, anyhow::Error> {
+  let path = crate::config::constant::target_root().join("carsier.flock");
+  std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path).with_context(|| format!("open {}", path.display()))
+}
+
+/// 64-bit FNV-1a, stable across rust releases since hashes are kept in fingerprint files
+pub fn hash_content(content: &[u8]) -> u64 {
+  content.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// split an `@file` argument file as scalac/javac do,
+/// whitespace separates args, double quoted args could contain escapes
+pub fn read_args_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>, anyhow::Error> {
+  let content = load_content(&path)?.ok_or_else(|| anyhow::Error::msg(format!("open {} failed", path.as_ref().display())))?;
+  let mut result = Vec::new();
+  let mut chars = content.chars();
+  let mut current: Option<String> = None;
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => {
+        let arg = current.get_or_insert_with(String::new);
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => if let Some(c) = chars.next() { arg.push(c) },
+            c => arg.push(c),
+          }
+        }
+      },
+      c if c.is_whitespace() => result.extend(current.take()),
+      c => current.get_or_insert_with(String::new).push(c),
+    }
+  }
+  result.extend(current.take());
+  Ok(result)
+}
+
+/// an empty dir under the system temp dir for a test, unique per process and `name`
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("carsier-test-{}-{}", std::process::id(), name));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).expect("create test dir");
+  dir
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use notify::Watcher;
+use crate::build;
+use crate::config::{PackageConfig, constant::*};
+
+/// events closer than this are handled as one change
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(Clap)]
+pub struct Opts {
+  #[clap(flatten)]
+  pub build: build::Opts,
+  /// run again whenever sources, resources or the config change
+  #[clap(long)]
+  pub watch: bool,
+}
+
+/// directories to watch, the config is watched through its parent
+/// since editors often replace files instead of writing them
+fn roots(config_path: &Path, config: &PackageConfig) -> Vec<(PathBuf, notify::RecursiveMode)> {
+  let parent = config_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+  let mut result = vec![
+    (parent.to_owned(), notify::RecursiveMode::NonRecursive),
+    (PathBuf::from("src"), notify::RecursiveMode::Recursive),
+  ];
+  for resource in &config.resources {
+    let root = resource.include.split('/').take_while(|s| !s.contains(|c| "*?[".contains(c))).collect::<Vec<_>>().join("/");
+    let root = if root.is_empty() { PathBuf::from(".") } else { PathBuf::from(root) };
+    if !result.iter().any(|(p, _)| root.starts_with(p) && p != Path::new(".")) {
+      result.push((root, notify::RecursiveMode::Recursive));
+    }
+  }
+  result
+}
+
+/// whether a change of `path`, relative to the workspace, could change the build
+fn relevant(path: &Path, config_path: &Path, config: &PackageConfig) -> bool {
+  if path.starts_with(target_root()) {
+    return false
+  }
+  path == config_path || path.starts_with("src") || config.resources.iter()
+    .filter_map(|r| glob::Pattern::new(&r.include).ok())
+    .any(|p| p.matches_path(path))
+}
+
+/// block until a relevant change, then collect further ones until it is quiet for `DEBOUNCE`
+fn wait<F: Fn(&Path) -> bool>(rx: &mpsc::Receiver<notify::Result<notify::Event>>, cwd: &Path, relevant: F) -> Result<BTreeSet<PathBuf>, anyhow::Error> {
+  let mut changed = BTreeSet::new();
+  loop {
+    let event = if changed.is_empty() {
+      rx.recv()?
+    } else {
+      match rx.recv_timeout(DEBOUNCE) {
+        Ok(event) => event,
+        Err(mpsc::RecvTimeoutError::Timeout) => return Ok(changed),
+        Err(e) => return Err(e.into()),
+      }
+    };
+    match event {
+      Ok(event) if !event.kind.is_access() => {
+        for path in event.paths {
+          let path = path.strip_prefix(cwd).map(|p| p.to_owned()).unwrap_or(path);
+          if relevant(&path) {
+            changed.insert(path);
+          }
+        }
+      },
+      Ok(_) => {},
+      Err(e) => warn!("watch: {}", e),
+    }
+  }
+}
+
+/// run `f` once, or with `--watch` again after every change until interrupted;
+/// the steps skip what is unchanged, so only dirty units and targets are redone
+pub fn main<F>(opts: Opts, config_path: &Path, config: PackageConfig, f: F) -> Result<(), anyhow::Error>
+  where F: Fn(build::Opts, &PackageConfig) -> Result<(), anyhow::Error> {
+  if !opts.watch {
+    return f(opts.build, &config)
+  }
+  let cwd = std::env::current_dir()?;
+  let terminal = std::io::stderr().is_terminal();
+  let (tx, rx) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(tx)?;
+  let mut watched: Vec<PathBuf> = Vec::new();
+  let mut config = config;
+  let mut run = true;
+  loop {
+    if run {
+      let start = Instant::now();
+      match f(opts.build.clone(), &config) {
+        Ok(()) => info!("finished in {:.1}s", start.elapsed().as_secs_f32()),
+        Err(e) => error!("{:#}", e),
+      }
+    }
+    for path in watched.drain(..) {
+      let _ = watcher.unwatch(&path);
+    }
+    for (path, mode) in roots(config_path, &config) {
+      if path.exists() {
+        watcher.watch(&path, mode)?;
+        watched.push(path);
+      }
+    }
+    info!("watching for changes, press ctrl-c to stop");
+    let changed = wait(&rx, &cwd, |p| relevant(p, config_path, &config))?;
+    if terminal {
+      eprint!("\x1b[2J\x1b[H");
+    }
+    info!("changed: {}", changed.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "));
+    run = true;
+    if changed.iter().any(|p| p == config_path) {
+      match crate::load_config(config_path) {
+        Ok(c) => config = c,
+        Err(e) => {
+          error!("{:#}", e);
+          run = false;
+        },
+      }
+    }
+  }
+}
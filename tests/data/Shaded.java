@@ -0,0 +1,16 @@
+package org.example.lib;
+
+public class Shaded implements java.io.Serializable {
+  public static final long BIG = 1L << 40;
+  public static final double RATIO = 0.75;
+  public static final String NAME = "org.example.lib.Shaded";
+  private Helper helper = new Helper();
+
+  public long next(long x) {
+    return x + BIG + helper.size();
+  }
+
+  static class Helper {
+    int size() { return 1; }
+  }
+}